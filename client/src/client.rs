@@ -1,66 +1,444 @@
 extern crate shared;
 
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Kills remembered for yanking, the oldest ones are forgotten first
+const KILL_RING_CAPACITY: usize = 16;
+const UNDO_CAPACITY: usize = 100;
+
+/// A line being edited. The cursor always sits on a grapheme cluster boundary, so that
+/// emoji sequences and combining characters are edited as a whole
 pub struct ClientInput {
-    // TODO: make private when the flux move is over
-    pub inner: Vec<char>,
+    text: String,
+    // Byte offset into the text
     cursor: usize,
+    kill_ring: Vec<String>,
+    // (text, cursor) snapshots taken before each edit
+    undo_stack: Vec<(String, usize)>,
+    // NOTE: typing a word is undone at once, like in readline
+    typing_word: bool,
+}
+
+fn is_word(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(char::is_alphanumeric)
+}
+
+fn is_space(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(char::is_whitespace)
+}
+
+/// Column, as a display width, and line of a byte offset into a text
+pub fn text_position(text: &str, offset: usize) -> (u16, u16) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before[line_start..].width() as u16,
+        before.matches('\n').count() as u16,
+    )
 }
 
 impl ClientInput {
     pub fn new() -> Self {
         Self {
-            inner: Vec::new(),
+            text: String::new(),
             cursor: 0,
+            kill_ring: Vec::new(),
+            undo_stack: Vec::new(),
+            typing_word: false,
+        }
+    }
+
+    /// Applies readline style editing keys, returns whether the key was one of them
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Char(c) if key.modifiers.is_empty() => self.insert(c),
+            KeyCode::Char(c) if key.modifiers == KeyModifiers::SHIFT => {
+                self.insert_uppercase(c.to_uppercase())
+            }
+            KeyCode::Char('a') if ctrl => self.home(),
+            KeyCode::Char('e') if ctrl => self.end(),
+            KeyCode::Char('b') if ctrl => self.left(),
+            KeyCode::Char('f') if ctrl => self.right(),
+            KeyCode::Char('d') if ctrl => self.backspace_forward(),
+            KeyCode::Char('h') if ctrl => self.backspace(),
+            KeyCode::Char('w') if ctrl => self.kill_word_backward(),
+            KeyCode::Char('u') if ctrl => self.kill_to_start(),
+            KeyCode::Char('k') if ctrl => self.kill_to_end(),
+            KeyCode::Char('y') if ctrl => self.yank(),
+            // NOTE: terminals report Ctrl-_ in several ways
+            KeyCode::Char('_' | '7' | '/' | 'z') if ctrl => self.undo(),
+            KeyCode::Char('b') if alt => self.word_left(),
+            KeyCode::Char('f') if alt => self.word_right(),
+            KeyCode::Char('d') if alt => self.kill_word_forward(),
+            KeyCode::Backspace if alt => self.kill_word_backward(),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.backspace_forward(),
+            KeyCode::Left if ctrl || alt => self.word_left(),
+            KeyCode::Right if ctrl || alt => self.word_right(),
+            KeyCode::Left => self.left(),
+            KeyCode::Right => self.right(),
+            KeyCode::Home => self.home(),
+            KeyCode::End => self.end(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn save_undo(&mut self) {
+        if self.undo_stack.len() == UNDO_CAPACITY {
+            self.undo_stack.remove(0);
+        }
+        self.undo_stack.push((self.text.clone(), self.cursor));
+        self.typing_word = false;
+    }
+
+    pub fn undo(&mut self) {
+        if let Some((text, cursor)) = self.undo_stack.pop() {
+            self.text = text;
+            self.cursor = cursor;
+        }
+        self.typing_word = false;
+    }
+
+    /// Moves back from the position while the graphemes match
+    fn scan_back(&self, mut position: usize, matches: fn(&str) -> bool) -> usize {
+        while let Some((start, grapheme)) = self.text[..position].grapheme_indices(true).next_back()
+        {
+            if !matches(grapheme) {
+                break;
+            }
+            position = start;
+        }
+        position
+    }
+
+    /// Moves forward from the position while the graphemes match
+    fn scan_forward(&self, mut position: usize, matches: fn(&str) -> bool) -> usize {
+        while let Some(grapheme) = self.text[position..].graphemes(true).next() {
+            if !matches(grapheme) {
+                break;
+            }
+            position += grapheme.len();
         }
+        position
+    }
+
+    fn previous_boundary(&self) -> usize {
+        self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(start, _)| start)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map_or(self.cursor, |grapheme| self.cursor + grapheme.len())
     }
 
     pub fn backspace(&mut self) {
         if self.cursor > 0 {
-            self.cursor -= 1;
-            self.inner.remove(self.cursor);
+            self.save_undo();
+            let from = self.previous_boundary();
+            self.text.drain(from..self.cursor);
+            self.cursor = from;
         }
     }
 
     pub fn backspace_forward(&mut self) {
-        if self.inner.len() > 0 && self.cursor < self.inner.len() {
-            self.inner.remove(self.cursor);
+        if self.cursor < self.text.len() {
+            self.save_undo();
+            let to = self.next_boundary();
+            self.text.drain(self.cursor..to);
         }
     }
 
     pub fn insert(&mut self, elem: char) {
-        self.inner.insert(self.cursor, elem);
-        self.cursor += 1;
+        if !self.typing_word || elem.is_whitespace() {
+            self.save_undo();
+        }
+        self.insert_str(elem.encode_utf8(&mut [0; 4]));
+        self.typing_word = !elem.is_whitespace();
     }
 
     pub fn insert_uppercase(&mut self, ch: std::char::ToUppercase) {
-        for (idx, c) in ch.enumerate() {
-            self.inner.insert(self.cursor + idx, c);
+        self.save_undo();
+        // NOTE: some letters turn into several when uppercased, e.g. ß into SS
+        self.insert_str(&ch.collect::<String>());
+    }
+
+    fn insert_str(&mut self, s: &str) {
+        self.text.insert_str(self.cursor, s);
+        self.cursor += s.len();
+        // NOTE: a combining character typed before another one may leave the cursor inside
+        // a cluster, in which case it goes past it
+        let start = self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(start, _)| start);
+        let end = self.text[start..]
+            .graphemes(true)
+            .next()
+            .map_or(start, |grapheme| start + grapheme.len());
+        if self.cursor != end && self.cursor != start {
+            self.cursor = end;
         }
-        self.cursor += 1;
     }
 
     pub fn left(&mut self) {
-        if self.cursor > 0 {
-            self.cursor -= 1;
-        }
+        self.cursor = self.previous_boundary();
+        self.typing_word = false;
     }
 
     pub fn right(&mut self) {
-        if self.cursor < self.inner.len() {
-            self.cursor += 1;
+        self.cursor = self.next_boundary();
+        self.typing_word = false;
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+        self.typing_word = false;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.text.len();
+        self.typing_word = false;
+    }
+
+    /// Offset of the grapheme at the display column, or of the end of the line if it's shorter
+    fn offset_at_column(&self, line_start: usize, line_end: usize, column: u16) -> usize {
+        let mut offset = line_start;
+        let mut width = 0;
+        for grapheme in self.text[line_start..line_end].graphemes(true) {
+            width += grapheme.width() as u16;
+            if width > column {
+                break;
+            }
+            offset += grapheme.len();
         }
+        offset
+    }
+
+    /// Moves to the line above, returns false if the cursor is on the first line
+    pub fn up(&mut self) -> bool {
+        let Some(line_end) = self.text[..self.cursor].rfind('\n') else {
+            return false;
+        };
+        let line_start = self.text[..line_end].rfind('\n').map_or(0, |i| i + 1);
+        self.cursor = self.offset_at_column(line_start, line_end, self.cursor_width());
+        self.typing_word = false;
+        true
+    }
+
+    /// Moves to the line below, returns false if the cursor is on the last line
+    pub fn down(&mut self) -> bool {
+        let Some(newline) = self.text[self.cursor..].find('\n') else {
+            return false;
+        };
+        let line_start = self.cursor + newline + 1;
+        let line_end = self.text[line_start..]
+            .find('\n')
+            .map_or(self.text.len(), |i| line_start + i);
+        self.cursor = self.offset_at_column(line_start, line_end, self.cursor_width());
+        self.typing_word = false;
+        true
+    }
+
+    /// Start of the current or previous word
+    fn word_start(&self) -> usize {
+        let position = self.scan_back(self.cursor, |g| !is_word(g));
+        self.scan_back(position, is_word)
+    }
+
+    /// End of the current or next word
+    fn word_end(&self) -> usize {
+        let position = self.scan_forward(self.cursor, |g| !is_word(g));
+        self.scan_forward(position, is_word)
+    }
+
+    pub fn word_left(&mut self) {
+        self.cursor = self.word_start();
+        self.typing_word = false;
+    }
+
+    pub fn word_right(&mut self) {
+        self.cursor = self.word_end();
+        self.typing_word = false;
+    }
+
+    /// Removes the text between the two positions into the kill ring
+    fn kill(&mut self, from: usize, to: usize) {
+        if from == to {
+            return;
+        }
+        self.save_undo();
+        let killed: String = self.text.drain(from..to).collect();
+        if self.kill_ring.len() == KILL_RING_CAPACITY {
+            self.kill_ring.remove(0);
+        }
+        self.kill_ring.push(killed);
+        self.cursor = from;
+    }
+
+    /// Like readline's Ctrl-W, the word here is anything up to the previous whitespace
+    pub fn kill_word_backward(&mut self) {
+        let from = self.scan_back(self.cursor, is_space);
+        let from = self.scan_back(from, |g| !is_space(g));
+        self.kill(from, self.cursor);
+    }
+
+    pub fn kill_word_forward(&mut self) {
+        self.kill(self.cursor, self.word_end());
+    }
+
+    pub fn kill_to_start(&mut self) {
+        self.kill(0, self.cursor);
+    }
+
+    pub fn kill_to_end(&mut self) {
+        self.kill(self.cursor, self.text.len());
+    }
+
+    /// Inserts the latest kill at the cursor
+    pub fn yank(&mut self) {
+        let Some(killed) = self.kill_ring.last().cloned() else {
+            return;
+        };
+        self.save_undo();
+        self.insert_str(&killed);
+    }
+
+    /// Replaces the whole text, e.g. with a line recalled from the history
+    pub fn set(&mut self, text: &str) {
+        self.save_undo();
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+    }
+
+    /// The whitespace delimited word ending at the cursor, along with where it starts
+    pub fn word_before_cursor(&self) -> (usize, &str) {
+        let start = self.scan_back(self.cursor, |g| !is_space(g));
+        (start, &self.text[start..self.cursor])
+    }
+
+    /// Replaces the text between the position and the cursor, e.g. with a completion
+    pub fn replace_before_cursor(&mut self, start: usize, text: &str) {
+        self.save_undo();
+        self.text.replace_range(start..self.cursor, text);
+        self.cursor = start + text.len();
     }
 
     pub fn clear(&mut self) {
-        self.inner.clear();
+        self.text.clear();
         self.cursor = 0;
+        self.undo_stack.clear();
+        self.typing_word = false;
+    }
+
+    /// Terminal columns taken by the text between the start of the line and the cursor
+    pub fn cursor_width(&self) -> u16 {
+        text_position(&self.text, self.cursor).0
+    }
+
+    /// Line the cursor is on, counted from 0
+    pub fn cursor_line(&self) -> u16 {
+        text_position(&self.text, self.cursor).1
+    }
+
+    /// Terminal columns taken by the whole text
+    pub fn width(&self) -> u16 {
+        self.text.width() as u16
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(text: &str) -> ClientInput {
+        let mut input = ClientInput::new();
+        text.chars().for_each(|c| input.insert(c));
+        input
+    }
+
+    fn text(input: &ClientInput) -> String {
+        input.as_str().to_string()
+    }
+
+    #[test]
+    fn test_kill_and_yank() {
+        let mut input = input("hello brave new-world");
+        input.kill_word_backward();
+        assert_eq!(text(&input), "hello brave ");
+        input.word_left();
+        assert_eq!(input.cursor_width(), 6);
+        input.kill_word_forward();
+        assert_eq!(text(&input), "hello  ");
+        input.end();
+        input.yank();
+        assert_eq!(text(&input), "hello  brave");
+        input.home();
+        input.kill_to_end();
+        assert!(text(&input).is_empty());
+        input.yank();
+        assert_eq!(text(&input), "hello  brave");
+        assert_eq!(input.cursor_width(), 12);
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut input = input("hello world");
+        input.kill_to_start();
+        input.undo();
+        assert_eq!(text(&input), "hello world");
+        // Typing is undone a word at a time
+        input.undo();
+        assert_eq!(text(&input), "hello ");
+        input.undo();
+        input.undo();
+        assert!(text(&input).is_empty());
     }
 
-    pub fn position(&self) -> usize {
-        self.cursor
+    #[test]
+    fn test_graphemes_and_width() {
+        // An e with a combining acute accent, an emoji and two wide CJK characters
+        let mut input = input("e\u{301}🎉日本");
+        assert_eq!(input.width(), 7);
+        input.left();
+        input.left();
+        assert_eq!(input.cursor_width(), 3);
+        input.backspace();
+        assert_eq!(text(&input), "e\u{301}日本");
+        input.backspace();
+        assert!(text(&input).starts_with('日'));
+        input.end();
+        input.insert_uppercase('ß'.to_uppercase());
+        assert_eq!(text(&input), "日本SS");
+        assert_eq!(input.cursor_width(), 6);
     }
 
-    pub fn get_ref(&self) -> &[char] {
-        &self.inner
+    #[test]
+    fn test_lines() {
+        let mut input = input("first line");
+        input.insert('\n');
+        "日本".chars().for_each(|c| input.insert(c));
+        assert_eq!((input.cursor_width(), input.cursor_line()), (4, 1));
+        assert!(input.up());
+        assert_eq!((input.cursor_width(), input.cursor_line()), (4, 0));
+        assert!(!input.up());
+        input.left();
+        // The wide character is not split, the cursor goes before it
+        assert!(input.down());
+        assert_eq!((input.cursor_width(), input.cursor_line()), (2, 1));
+        assert!(!input.down());
     }
 }
@@ -6,16 +6,20 @@ mod client;
 mod state;
 mod ui;
 
+use crate::state::chat::ChatLogConfig;
 use crate::state::state_manager::StateManager;
+use crate::ui::theme::ThemeConfig;
 use crate::ui::ui_manager::UiManager;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    ChatLogConfig::from_env()?.install();
+    let theme_config = ThemeConfig::load()?;
     let (state_tx, state_rx) = mpsc::unbounded_channel();
     let (action_tx, action_rx) = mpsc::unbounded_channel();
     let (termination_tx, mut termination_rx) = broadcast::channel(1);
     let mut state_manager = StateManager::new(state_tx);
-    let mut ui_manager = UiManager::new(action_tx);
+    let mut ui_manager = UiManager::new(action_tx, theme_config);
 
     let termination_rx_ui = termination_tx.subscribe();
     let state_join_handle =
@@ -1,7 +1,44 @@
-#[derive(Clone, Debug)]
+use shared::message::Presence;
+
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) enum Action {
-    ConnectAndLogin { name: String },
-    SendMessage { message: String },
+    ConnectAndLogin {
+        name: String,
+    },
+    SendMessage {
+        message: String,
+    },
+    SendDirectMessage {
+        to: String,
+        message: String,
+    },
+    Emote {
+        action: String,
+    },
+    ChangeNick {
+        name: String,
+    },
+    // A local message for the user only, e.g. the result of a command
+    ShowSystemMessage {
+        message: String,
+    },
+    React {
+        message_id: u64,
+        emoji: String,
+    },
+    Typing,
+    SetStatus {
+        presence: Presence,
+        status_text: String,
+    },
+    // Joins the room and talks there, no room means back to the main chat
+    JoinRoom {
+        room: Option<String>,
+    },
+    // No room means the one currently talked in
+    PartRoom {
+        room: Option<String>,
+    },
     Quit,
 }
 
@@ -10,6 +47,26 @@ impl std::fmt::Display for Action {
         match self {
             Self::ConnectAndLogin { name } => write!(f, "Connect and login @{name}"),
             Self::SendMessage { message } => write!(f, "Send message '{message}'"),
+            Self::SendDirectMessage { to, message } => {
+                write!(f, "Send direct message '{message}' to @{to}")
+            }
+            Self::Emote { action } => write!(f, "Emote '{action}'"),
+            Self::ChangeNick { name } => write!(f, "Change nick to @{name}"),
+            Self::ShowSystemMessage { message } => write!(f, "Show system message '{message}'"),
+            Self::React { message_id, emoji } => write!(f, "React {emoji} to #{message_id}"),
+            Self::Typing => write!(f, "Typing"),
+            Self::SetStatus {
+                presence,
+                status_text,
+            } => write!(f, "Set status {presence} '{status_text}'"),
+            Self::JoinRoom { room } => match room {
+                Some(room) => write!(f, "Join {room}"),
+                None => write!(f, "Go back to the main chat"),
+            },
+            Self::PartRoom { room } => match room {
+                Some(room) => write!(f, "Part {room}"),
+                None => write!(f, "Part the current room"),
+            },
             Self::Quit => write!(f, "Quit"),
         }
     }
@@ -1,47 +1,448 @@
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use chrono::NaiveTime;
 use ratatui::layout::Rect;
-use std::collections::VecDeque;
+use shared::parse_async::Frame;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::state::markdown::{self, Format};
+use crate::state::spill::Spill;
+
+const BUFFER_ENV: &str = "CHAD_CHAT_BUFFER";
+// Directory older messages are spilled into, spilling is off if unset
+const SPILL_ENV: &str = "CHAD_CHAT_SPILL";
+
+static CONFIG: OnceLock<ChatLogConfig> = OnceLock::new();
 
 pub(crate) static USER_ICON: &str = " ";
 pub(crate) static SYSTEM_ICON: &str = " ";
+pub(crate) static DIRECT_ICON: &str = " ";
 
 #[derive(Debug, Clone)]
 pub(crate) struct ChatMessage {
+    // NOTE: only messages relayed by the server have an id, system messages don't
+    id: Option<u64>,
     timestamp: String,
     user_name: String,
     msg: String,
     icon: String,
+    reactions: Vec<(String, u64)>,
+    // Whether the message @mentions the logged in user
+    mentions_me: bool,
 }
 
 impl ChatMessage {
     pub(crate) fn new(user_name: String, timestamp: String, msg: String, icon: String) -> Self {
         Self {
+            id: None,
             timestamp,
             user_name,
             msg,
             icon,
+            reactions: Vec::new(),
+            mentions_me: false,
         }
     }
 
-    pub(crate) fn length(&self) -> u16 {
-        // Two spaces and a colon
-        (self.timestamp.len() + self.user_name.len() + self.msg.len() + self.icon.len() + 3) as u16
+    pub(crate) fn with_id(mut self, id: u64) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub(crate) fn with_mention(mut self, mentions_me: bool) -> Self {
+        self.mentions_me = mentions_me;
+        self
+    }
+
+    pub(crate) fn mentions_me(&self) -> bool {
+        self.mentions_me
+    }
+
+    /// The timestamp and author part, e.g. "12:00:00 bob: "
+    fn prefix(&self) -> String {
+        let separator = if self.msg.starts_with("/me ") {
+            " "
+        } else {
+            ": "
+        };
+        format!("{} {}{}", self.timestamp, self.byline(), separator)
+    }
+
+    /// The author with their icon, e.g. "bob", or "* bob" for an emote
+    pub(crate) fn byline(&self) -> String {
+        match self.msg.strip_prefix("/me ") {
+            Some(_) => format!("{}* {}", self.icon, self.user_name),
+            None => format!("{}{}", self.icon, self.user_name),
+        }
+    }
+
+    pub(crate) fn timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    /// Who sent the message, direct and room messages are authored "from → to"
+    pub(crate) fn sender(&self) -> &str {
+        self.user_name
+            .split(" → ")
+            .next()
+            .unwrap_or(&self.user_name)
+    }
+
+    /// Notices and errors shown by the client itself
+    pub(crate) fn is_system(&self) -> bool {
+        self.icon == SYSTEM_ICON
+    }
+
+    fn body(&self) -> &str {
+        self.msg.strip_prefix("/me ").unwrap_or(&self.msg)
+    }
+
+    /// The message word wrapped to the width, the lines after the first are indented to
+    /// start under the text rather than under the timestamp
+    pub(crate) fn wrap(&self, width: u16) -> Vec<WrappedLine> {
+        wrap(&self.prefix(), &markdown::parse(self.body()), width)
+    }
+
+    /// Encodes the message for the spill file, along with its reactions
+    pub(crate) fn to_frame(&self) -> Frame {
+        let mut reactions = Frame::array();
+        self.reactions.iter().for_each(|(emoji, count)| {
+            reactions.push_bulk(Frame::Bulk(emoji.clone().into()));
+            reactions.push_bulk(Frame::Bulk(count.to_string().into()));
+        });
+        let mut frame = Frame::array();
+        frame.push_bulk(Frame::Bulk(
+            self.id.map(|id| id.to_string()).unwrap_or_default().into(),
+        ));
+        frame.push_bulk(Frame::Bulk(self.timestamp.clone().into()));
+        frame.push_bulk(Frame::Bulk(self.user_name.clone().into()));
+        frame.push_bulk(Frame::Bulk(self.msg.clone().into()));
+        frame.push_bulk(Frame::Bulk(self.icon.clone().into()));
+        frame.push_bulk(reactions);
+        frame.push_bulk(Frame::Bulk(Bytes::from_static(if self.mentions_me {
+            b"1"
+        } else {
+            b"0"
+        })));
+        frame
+    }
+
+    /// Decodes a message encoded by `to_frame`
+    pub(crate) fn from_frame(frame: Frame) -> Result<Self> {
+        let Frame::Array(fields) = frame else {
+            bail!("Expected a message array");
+        };
+        let Ok([id, timestamp, user_name, msg, icon, reactions, mentions_me]) =
+            <[Frame; 7]>::try_from(fields)
+        else {
+            bail!("Expected 7 message fields");
+        };
+        let Frame::Array(reactions) = reactions else {
+            bail!("Expected a reactions array");
+        };
+        let mut counts = Vec::new();
+        let mut reactions = reactions.into_iter();
+        while let (Some(emoji), Some(count)) = (reactions.next(), reactions.next()) {
+            counts.push((into_string(emoji)?, into_string(count)?.parse()?));
+        }
+        let id = into_string(id)?;
+        Ok(Self {
+            id: if id.is_empty() {
+                None
+            } else {
+                Some(id.parse()?)
+            },
+            timestamp: into_string(timestamp)?,
+            user_name: into_string(user_name)?,
+            msg: into_string(msg)?,
+            icon: into_string(icon)?,
+            reactions: counts,
+            mentions_me: into_string(mentions_me)? == "1",
+        })
+    }
+
+    /// Reaction counts rendered under the message, e.g. "👍 2  🎉 1"
+    pub(crate) fn reactions_line(&self) -> Option<String> {
+        if self.reactions.is_empty() {
+            return None;
+        }
+        Some(
+            self.reactions
+                .iter()
+                .map(|(emoji, count)| format!("{emoji} {count}"))
+                .collect::<Vec<_>>()
+                .join("  "),
+        )
     }
 }
 
 impl std::fmt::Display for ChatMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{} {}{}: {}",
-            self.timestamp, self.icon, self.user_name, self.msg
-        )
+        write!(f, "{}{}", self.prefix(), self.body())
     }
 }
 
+/// What to look for in the chat log, e.g. "from:bob after:12:00 before:13:30 link". The
+/// times are compared with the time of day the messages were sent, "12" means from noon on
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct SearchQuery {
+    pub(crate) text: String,
+    pub(crate) author: Option<String>,
+    pub(crate) after: Option<NaiveTime>,
+    pub(crate) before: Option<NaiveTime>,
+}
+
+impl SearchQuery {
+    pub(crate) fn parse(source: &str) -> Self {
+        let mut query = Self::default();
+        let mut words = Vec::new();
+        for word in source.split_whitespace() {
+            if let Some(author) = word.strip_prefix("from:") {
+                query.author = Some(author.trim_start_matches('@').to_string());
+            } else if let Some(after) = word.strip_prefix("after:").and_then(parse_time) {
+                query.after = Some(after);
+            } else if let Some(before) = word.strip_prefix("before:").and_then(parse_time) {
+                query.before = Some(before);
+            } else {
+                words.push(word);
+            }
+        }
+        query.text = words.join(" ");
+        query
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub(crate) fn matches(&self, message: &ChatMessage) -> bool {
+        // NOTE: messages from another day carry their date before the time
+        let sent_at = message.timestamp.rsplit(' ').next().and_then(parse_time);
+        self.author
+            .as_ref()
+            .is_none_or(|author| message.sender().eq_ignore_ascii_case(author))
+            && self
+                .after
+                .is_none_or(|after| sent_at.is_some_and(|sent_at| sent_at >= after))
+            && self
+                .before
+                .is_none_or(|before| sent_at.is_some_and(|sent_at| sent_at < before))
+            && (self.text.is_empty() || !find_ignore_case(&message.msg, &self.text).is_empty())
+    }
+}
+
+/// Byte ranges of the case insensitive occurrences of the needle
+pub(crate) fn find_ignore_case(haystack: &str, needle: &str) -> Vec<std::ops::Range<usize>> {
+    let mut found = Vec::new();
+    if needle.is_empty() {
+        return found;
+    }
+    let mut start = 0;
+    while start < haystack.len() {
+        let mut rest = haystack[start..].char_indices();
+        let mut needle_chars = needle.chars();
+        let end = loop {
+            match (needle_chars.next(), rest.next()) {
+                (None, Some((offset, _))) => break Some(start + offset),
+                (None, None) => break Some(haystack.len()),
+                (Some(n), Some((_, h))) if n.to_lowercase().eq(h.to_lowercase()) => {}
+                _ => break None,
+            }
+        };
+        match end {
+            Some(end) => {
+                found.push(start..end);
+                start = end;
+            }
+            None => start += haystack[start..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    found
+}
+
+/// A time of day as "hh:mm:ss", "hh:mm" or just the hour
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .ok()
+        .or_else(|| NaiveTime::from_hms_opt(time.parse().ok()?, 0, 0))
+}
+
+fn into_string(frame: Frame) -> Result<String> {
+    match frame {
+        Frame::Bulk(bytes) => Ok(String::from_utf8(bytes.to_vec())?),
+        Frame::Array(_) => bail!("Expected a bulk string"),
+    }
+}
+
+/// A wrapped line, in runs of the same format
+pub(crate) type WrappedLine = Vec<(String, Format)>;
+
+/// A line being filled by `wrap`
+struct LineBuilder {
+    runs: WrappedLine,
+    width: usize,
+}
+
+impl LineBuilder {
+    fn new(text: &str) -> Self {
+        Self {
+            runs: vec![(text.to_string(), Format::default())],
+            width: text.width(),
+        }
+    }
+
+    fn push(&mut self, text: &str, format: Format) {
+        match self.runs.last_mut() {
+            Some((last, last_format)) if *last_format == format => last.push_str(text),
+            _ => self.runs.push((text.to_string(), format)),
+        }
+        self.width += text.width();
+    }
+
+    /// Fills the rest of a code block line so that the block's background is even
+    fn pad(&mut self, width: usize, format: Format) {
+        let padding = width.saturating_sub(self.width);
+        self.push(&" ".repeat(padding), format);
+    }
+
+    /// The finished line, with a new one started at the indent
+    fn take(&mut self, indent: usize) -> WrappedLine {
+        let line = std::mem::replace(self, Self::new(&" ".repeat(indent)));
+        line.runs
+            .into_iter()
+            .filter(|(text, _)| !text.is_empty())
+            .collect()
+    }
+}
+
+/// Greedy word wrapping by display width. Words wider than a line are broken between
+/// graphemes, and the lines after the first are indented by the width of the prefix.
+/// Code block lines are broken between graphemes only, to keep their whitespace
+pub(crate) fn wrap(prefix: &str, runs: &[(String, Format)], width: u16) -> Vec<WrappedLine> {
+    let width = width.max(1) as usize;
+    // NOTE: in a narrow area the indent would leave no room for the text
+    let indent = match prefix.width() {
+        indent if indent * 2 > width => 0,
+        indent => indent,
+    };
+    let mut text_lines = vec![Vec::new()];
+    for (text, format) in runs {
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                text_lines.push(Vec::new());
+            }
+            let text_line = text_lines.last_mut().expect("there is always a line");
+            // NOTE: marks empty code block lines, so that they get padded too
+            if format.code_block {
+                text_line.push(("", *format));
+            }
+            text_line.extend(part.graphemes(true).map(|grapheme| (grapheme, *format)));
+        }
+    }
+    let mut lines = Vec::new();
+    let mut line = LineBuilder::new(prefix);
+    for (i, text_line) in text_lines.iter().enumerate() {
+        if i > 0 {
+            lines.push(line.take(indent));
+        }
+        if let Some((_, format)) = text_line.iter().find(|(_, format)| format.code_block) {
+            for (grapheme, format) in text_line {
+                if line.width + grapheme.width() > width && line.width > indent {
+                    line.pad(width, *format);
+                    lines.push(line.take(indent));
+                }
+                line.push(grapheme, *format);
+            }
+            line.pad(width, *format);
+            continue;
+        }
+        let mut line_empty = true;
+        let mut separator_format = Format::default();
+        let mut start = 0;
+        for word in text_line.split(|(grapheme, _)| *grapheme == " ") {
+            let word_width: usize = word.iter().map(|(grapheme, _)| grapheme.width()).sum();
+            let separator = usize::from(!line_empty);
+            if !line_empty && line.width + separator + word_width > width {
+                lines.push(line.take(indent));
+            } else if !line_empty {
+                line.push(" ", separator_format);
+            }
+            for (grapheme, format) in word {
+                if line.width + grapheme.width() > width && line.width > indent {
+                    lines.push(line.take(indent));
+                }
+                line.push(grapheme, *format);
+            }
+            line_empty = false;
+            start += word.len();
+            if let Some((_, format)) = text_line.get(start) {
+                separator_format = *format;
+            }
+            start += 1;
+        }
+    }
+    lines.push(line.take(indent));
+    lines
+}
+
 #[derive(Clone)]
 pub(crate) struct ChatLog {
     messages: VecDeque<ChatMessage>,
     max_messages: usize,
+    // Id of the first message that arrived while the user was away, a divider goes above it
+    new_messages_from: Option<u64>,
+    // Messages put in the log so far, including the ones that no longer fit in it
+    messages_received: u64,
+    // NOTE: the spill is shared by the clones of the log, each of them only reads the
+    // messages that had been spilled as of its cloning
+    spill: Option<Arc<Mutex<Spill>>>,
+    spilled: usize,
+    // Newest message id seen per room, the main chat under "", spilled messages included
+    newest_ids: HashMap<String, u64>,
+}
+
+/// How many messages the chat log keeps in memory, and where the older ones go
+#[derive(Clone, Debug)]
+pub(crate) struct ChatLogConfig {
+    pub(crate) max_messages: usize,
+    pub(crate) spill_dir: Option<PathBuf>,
+}
+
+impl Default for ChatLogConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: 50,
+            spill_dir: None,
+        }
+    }
+}
+
+impl ChatLogConfig {
+    /// The defaults, overridden by whichever of the environment variables are set
+    pub(crate) fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+        if let Ok(max_messages) = std::env::var(BUFFER_ENV) {
+            config.max_messages = max_messages.parse().context(BUFFER_ENV)?;
+            if config.max_messages == 0 {
+                bail!("{} must be at least 1", BUFFER_ENV);
+            }
+        }
+        if let Ok(dir) = std::env::var(SPILL_ENV) {
+            std::fs::create_dir_all(&dir).context(SPILL_ENV)?;
+            config.spill_dir = Some(dir.into());
+        }
+        Ok(config)
+    }
+
+    /// Sets the config the chat logs are created with, only the first call has an effect
+    pub(crate) fn install(self) {
+        let _ = CONFIG.set(self);
+    }
 }
 
 impl ChatLog {
@@ -49,49 +450,376 @@ impl ChatLog {
         Self {
             messages: VecDeque::new(),
             max_messages,
+            new_messages_from: None,
+            messages_received: 0,
+            spill: None,
+            spilled: 0,
+            newest_ids: HashMap::new(),
         }
     }
 
+    /// Spills the messages that no longer fit into a file in the directory, rather than
+    /// forgetting them
+    pub(crate) fn with_spill(mut self, dir: &Path) -> Result<Self> {
+        self.spill = Some(Arc::new(Mutex::new(Spill::create(dir)?)));
+        Ok(self)
+    }
+
     pub(crate) fn put_message(&mut self, line: ChatMessage) {
+        self.messages_received += 1;
         if self.messages.len() + 1 > self.max_messages {
-            self.messages.pop_front();
-            self.messages.push_back(line)
-        } else {
-            self.messages.push_back(line);
+            if let Some(oldest) = self.messages.pop_front() {
+                self.spill_message(&oldest);
+            }
         }
+        self.messages.push_back(line);
     }
 
+    fn spill_message(&mut self, message: &ChatMessage) {
+        let Some(spill) = &self.spill else {
+            return;
+        };
+        let mut spill = spill.lock().expect("Spill lock poisoned");
+        // NOTE: if the file can't be written to anymore, older messages are forgotten
+        // like they would be without spilling
+        match spill.push(message) {
+            Ok(()) => self.spilled = spill.len(),
+            Err(_) => {
+                drop(spill);
+                self.spill = None;
+                self.spilled = 0;
+            }
+        }
+    }
+
+    /// A spilled message, counted from the oldest one
+    fn spilled_message(&self, index: usize) -> Option<ChatMessage> {
+        let mut spill = self.spill.as_ref()?.lock().expect("Spill lock poisoned");
+        spill.get(index).ok()
+    }
+
+    /// A message, counted from the oldest one whether spilled or not
+    fn message(&self, index: usize) -> Option<ChatMessage> {
+        match index.checked_sub(self.spilled) {
+            Some(index) => self.messages.get(index).cloned(),
+            None => self.spilled_message(index),
+        }
+    }
+
+    /// The messages from `skip` messages before the newest one back. Spilled messages are
+    /// only read once the iteration gets to them
+    fn iter_newest(&self, skip: usize) -> impl Iterator<Item = ChatMessage> + '_ {
+        (0..self.len().saturating_sub(skip))
+            .rev()
+            .filter_map(|index| self.message(index))
+    }
+
+    /// All the messages, the oldest first
+    fn iter_oldest(&self) -> impl Iterator<Item = ChatMessage> + '_ {
+        (0..self.len()).filter_map(|index| self.message(index))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.spilled + self.messages.len()
+    }
+
+    /// Sequence number of the oldest message still in the log, messages are numbered in
+    /// the order they were put in the log
+    pub(crate) fn first_sequence(&self) -> u64 {
+        self.messages_received - self.len() as u64
+    }
+
+    /// Sequence numbers of the matching messages, the oldest first
+    pub(crate) fn search(&self, query: &SearchQuery) -> Vec<u64> {
+        let first = self.first_sequence();
+        self.iter_oldest()
+            .enumerate()
+            .filter(|(_, m)| query.matches(m))
+            .map(|(index, _)| first + index as u64)
+            .collect()
+    }
+
+    /// The scroll that brings the message to the bottom of the view, `None` if the message
+    /// is no longer in the log
+    pub(crate) fn scroll_to(&self, sequence: u64) -> Option<usize> {
+        let index = sequence.checked_sub(self.first_sequence())? as usize;
+        (index < self.len()).then(|| self.len() - 1 - index)
+    }
+
+    pub(crate) fn set_reactions(&mut self, message_id: u64, reactions: Vec<(String, u64)>) {
+        if let Some(m) = self
+            .messages
+            .iter_mut()
+            .rev()
+            .find(|m| m.id == Some(message_id))
+        {
+            m.reactions = reactions;
+        }
+    }
+
+    pub(crate) fn set_new_messages_from(&mut self, message_id: Option<u64>) {
+        self.new_messages_from = message_id;
+    }
+
+    /// Whether the "new messages" divider goes right above this message
+    pub(crate) fn is_first_new(&self, message: &ChatMessage) -> bool {
+        message.id.is_some() && message.id == self.new_messages_from
+    }
+
+    /// Records the message id as seen in the room, false if the room had it already. The
+    /// server hands out ids in order, so anything not newer than the newest one was seen
+    pub(crate) fn see(&mut self, room: &str, message_id: u64) -> bool {
+        match self.newest_ids.get_mut(room) {
+            Some(newest) if message_id <= *newest => false,
+            Some(newest) => {
+                *newest = message_id;
+                true
+            }
+            None => {
+                self.newest_ids.insert(room.to_string(), message_id);
+                true
+            }
+        }
+    }
+
+    pub(crate) fn messages_received(&self) -> u64 {
+        self.messages_received
+    }
+
+    pub(crate) fn last_message_id(&self) -> Option<u64> {
+        self.messages.iter().rev().find_map(|m| m.id)
+    }
+
+    #[allow(dead_code)]
     pub(crate) fn get_messages(&self) -> &VecDeque<ChatMessage> {
         &self.messages
     }
 
-    pub(crate) fn get_fitting_messages(&self, area: &Rect) -> VecDeque<ChatMessage> {
+    /// Rows the message takes when drawn, with its reactions and the divider above it
+    fn lines_needed(&self, message: &ChatMessage, width: u16) -> u16 {
+        // TODO: probably better to allocate once
+        let mut lines_needed = message.wrap(width).len() as u16;
+        if message.reactions_line().is_some() {
+            lines_needed += 1;
+        }
+        if self.is_first_new(message) {
+            lines_needed += 1;
+        }
+        lines_needed
+    }
+
+    /// The messages that fit in the area, ending `skip` messages before the newest one. The
+    /// newest of them is there even if it doesn't fit, it's drawn clipped to its last lines
+    pub(crate) fn get_fitting_messages(&self, area: &Rect, skip: usize) -> VecDeque<ChatMessage> {
         let mut fitting_messages: VecDeque<ChatMessage> = VecDeque::new();
         let mut lines_filled: u16 = 0;
 
-        for m in self.messages.iter().rev() {
-            // TODO: probably better to allocate once
-            let lines_needed = divide_ceiled(m.length() as f32, area.width as f32);
-            if (lines_needed + lines_filled) > area.height {
+        for m in self.iter_newest(skip) {
+            let lines_needed = self.lines_needed(&m, area.width);
+            if !fitting_messages.is_empty()
+                && lines_needed.saturating_add(lines_filled) > area.height
+            {
                 break;
-            } else {
-                lines_filled += lines_needed;
-                fitting_messages.push_front(m.clone());
             }
+            lines_filled = lines_filled.saturating_add(lines_needed);
+            fitting_messages.push_front(m);
         }
         fitting_messages
     }
-}
 
-fn divide_ceiled(nom: f32, denom: f32) -> u16 {
-    (nom / denom).ceil() as u16
+    /// The largest `skip` that still fills the area, i.e. shows the oldest messages. Only the
+    /// oldest messages that fill the area are read
+    pub(crate) fn max_scroll(&self, area: &Rect) -> usize {
+        let mut lines_filled: u16 = 0;
+        let fitting = self
+            .iter_oldest()
+            .take_while(|m| {
+                lines_filled = lines_filled.saturating_add(self.lines_needed(m, area.width));
+                lines_filled <= area.height
+            })
+            .count();
+        // NOTE: an oldest message taller than the area is still shown, clipped
+        self.len().saturating_sub(fitting.max(1))
+    }
 }
 
 impl Default for ChatLog {
+    /// A log as configured with `ChatLogConfig::install`
     fn default() -> Self {
-        Self {
-            messages: VecDeque::new(),
-            max_messages: 50,
+        let config = CONFIG.get_or_init(ChatLogConfig::default);
+        match &config.spill_dir {
+            // NOTE: the directory was checked at startup, failing here is unlikely and the
+            // log still works without spilling
+            Some(dir) => Self::new(config.max_messages)
+                .with_spill(dir)
+                .unwrap_or_else(|_| Self::new(config.max_messages)),
+            None => Self::new(config.max_messages),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wrap_plain(prefix: &str, text: &str, width: u16) -> Vec<String> {
+        wrap(prefix, &[(text.to_string(), Format::default())], width)
+            .into_iter()
+            .map(|line| line.into_iter().map(|(text, _)| text).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(
+            wrap_plain("12:00 bob: ", "the quick brown fox jumps", 24),
+            vec![
+                "12:00 bob: the quick",
+                "           brown fox",
+                "           jumps"
+            ]
+        );
+        // Wide characters are measured by their display width
+        assert_eq!(
+            wrap_plain("bob: ", "日本語のテキスト", 15),
+            vec!["bob: 日本語のテ", "     キスト"]
+        );
+        assert_eq!(
+            wrap_plain("bob: ", "a\n\nb", 20),
+            vec!["bob: a", "     ", "     b"]
+        );
+        // The indent is dropped when it would take most of the line
+        assert_eq!(
+            wrap_plain("bob: ", "abcdefgh", 8),
+            vec!["bob: abc", "defgh"]
+        );
+        // Code blocks keep their whitespace and are padded to the width
+        let code_block = Format {
+            code_block: true,
+            ..Format::default()
+        };
+        assert_eq!(
+            wrap("bob: ", &markdown::parse("```\n  a  b\n\n```"), 12),
+            vec![
+                vec![("bob: ".to_string(), Format::default())],
+                vec![
+                    ("     ".to_string(), Format::default()),
+                    ("  a  b ".to_string(), code_block)
+                ],
+                vec![
+                    ("     ".to_string(), Format::default()),
+                    ("       ".to_string(), code_block)
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scrolling() {
+        let mut log = ChatLog::default();
+        for i in 0..10 {
+            log.put_message(ChatMessage::new(
+                "bob".to_string(),
+                "12:00".to_string(),
+                format!("message {i}"),
+                String::new(),
+            ));
+        }
+        let area = Rect::new(0, 0, 40, 4);
+        let newest = log.get_fitting_messages(&area, 0);
+        assert_eq!(newest.len(), 4);
+        assert_eq!(newest.back().unwrap().body(), "message 9");
+        assert_eq!(log.max_scroll(&area), 6);
+        let oldest = log.get_fitting_messages(&area, log.max_scroll(&area));
+        assert_eq!(oldest.front().unwrap().body(), "message 0");
+        assert_eq!(oldest.len(), 4);
+    }
+
+    #[test]
+    fn test_tall_message() {
+        let mut log = ChatLog::default();
+        for msg in ["short", "one\ntwo\nthree\nfour\nfive"] {
+            log.put_message(ChatMessage::new(
+                "bob".to_string(),
+                "12:00".to_string(),
+                msg.to_string(),
+                String::new(),
+            ));
+        }
+        let area = Rect::new(0, 0, 40, 3);
+        let newest = log.get_fitting_messages(&area, 0);
+        assert_eq!(newest.len(), 1);
+        assert_eq!(newest[0].body(), "one\ntwo\nthree\nfour\nfive");
+        assert_eq!(log.max_scroll(&area), 1);
+    }
+
+    #[test]
+    fn test_spill() {
+        let dir = std::env::temp_dir();
+        let mut log = ChatLog::new(2).with_spill(&dir).unwrap();
+        for i in 0..5 {
+            let message = ChatMessage::new(
+                "bob".to_string(),
+                "12:00".to_string(),
+                format!("message {i}\nwith a second line"),
+                USER_ICON.to_string(),
+            )
+            .with_id(i)
+            .with_mention(i == 1);
+            log.put_message(message);
+        }
+        assert_eq!(log.messages.len(), 2);
+        let messages: Vec<ChatMessage> = log.iter_oldest().collect();
+        assert_eq!(messages.len(), 5);
+        assert_eq!(
+            messages[1].to_string(),
+            format!("12:00 {USER_ICON}bob: message 1\nwith a second line")
+        );
+        assert!(messages[1].mentions_me());
+        assert_eq!(messages[1].id, Some(1));
+        let area = Rect::new(0, 0, 80, 4);
+        assert_eq!(log.max_scroll(&area), 3);
+        let oldest = log.get_fitting_messages(&area, 3);
+        assert_eq!(oldest.front().unwrap().id, Some(0));
+    }
+
+    #[test]
+    fn test_see() {
+        let mut log = ChatLog::new(1);
+        assert!(log.see("", 2));
+        assert!(log.see("#rust", 1));
+        assert!(!log.see("", 1));
+        assert!(!log.see("", 2));
+        assert!(log.see("", 3));
+        assert!(!log.see("#rust", 1));
+    }
+
+    #[test]
+    fn test_search() {
+        let query = SearchQuery::parse("from:@Bob after:12 the  link");
+        assert_eq!(query.author.as_deref(), Some("Bob"));
+        assert_eq!(query.after, NaiveTime::from_hms_opt(12, 0, 0));
+        assert_eq!(query.text, "the link");
+        assert_eq!(SearchQuery::parse("after:noon").text, "after:noon");
+
+        let mut log = ChatLog::new(10);
+        for (time, name, msg) in [
+            ("2024-06-01 11:59:00", "bob", "here is the LINK"),
+            ("12:30:00", "alice", "the link is broken"),
+            ("12:31:00", "bob", "The Link, fixed"),
+        ] {
+            log.put_message(ChatMessage::new(
+                name.to_string(),
+                time.to_string(),
+                msg.to_string(),
+                String::new(),
+            ));
         }
+        assert_eq!(log.search(&query), vec![2]);
+        assert_eq!(log.search(&SearchQuery::parse("link")), vec![0, 1, 2]);
+        assert_eq!(log.search(&SearchQuery::parse("before:12:30")), vec![0]);
+        assert_eq!(log.search(&SearchQuery::parse("after:9:05")), vec![0, 1, 2]);
+        assert_eq!(log.scroll_to(0), Some(2));
+        assert_eq!(find_ignore_case("Ünïcode ünï", "ÜNÏ"), vec![0..5, 10..15]);
     }
 }
@@ -0,0 +1,188 @@
+// Inline delimiters, "**" goes before "*" so that bold isn't taken for two italics
+const DELIMITERS: &[&str] = &["`", "**", "~~", "*"];
+const FENCE: &str = "```";
+
+/// How a piece of a message is drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Format {
+    pub(crate) bold: bool,
+    pub(crate) italic: bool,
+    pub(crate) strike: bool,
+    pub(crate) code: bool,
+    // A line of a fenced code block, it's drawn as it is and never word wrapped
+    pub(crate) code_block: bool,
+}
+
+impl Format {
+    fn with(self, delimiter: &str) -> Self {
+        match delimiter {
+            "**" => Self { bold: true, ..self },
+            "~~" => Self {
+                strike: true,
+                ..self
+            },
+            "*" => Self {
+                italic: true,
+                ..self
+            },
+            _ => Self { code: true, ..self },
+        }
+    }
+}
+
+/// The text of a message as it's shown, split in runs of the same format. The delimiters
+/// are dropped, and a code block always starts on a line of its own
+// NOTE: only the display is affected, the message goes on the wire as it was typed
+pub(crate) fn parse(text: &str) -> Vec<(String, Format)> {
+    let mut runs = Vec::new();
+    let mut code: Option<Vec<&str>> = None;
+    let mut first = true;
+    for line in text.split('\n') {
+        let fence = line.trim_start().starts_with(FENCE);
+        match &mut code {
+            Some(code_lines) if fence => {
+                push_code_block(&mut runs, code_lines);
+                code = None;
+                first = false;
+            }
+            Some(code_lines) => code_lines.push(line),
+            None if fence => code = Some(Vec::new()),
+            None => {
+                if !first {
+                    push(&mut runs, "\n", Format::default());
+                }
+                first = false;
+                inline(line, Format::default(), &mut runs);
+            }
+        }
+    }
+    // NOTE: like in markdown, a fence that's never closed runs to the end of the message
+    if let Some(code_lines) = code {
+        push_code_block(&mut runs, &code_lines);
+    }
+    runs
+}
+
+fn push_code_block(runs: &mut Vec<(String, Format)>, code_lines: &[&str]) {
+    push(runs, "\n", Format::default());
+    runs.push((
+        code_lines.join("\n"),
+        Format {
+            code_block: true,
+            ..Format::default()
+        },
+    ));
+}
+
+/// Appends to the last run if it has the same format
+fn push(runs: &mut Vec<(String, Format)>, text: &str, format: Format) {
+    if text.is_empty() {
+        return;
+    }
+    match runs.last_mut() {
+        Some((last, last_format)) if *last_format == format && !format.code_block => {
+            last.push_str(text)
+        }
+        _ => runs.push((text.to_string(), format)),
+    }
+}
+
+fn inline(text: &str, format: Format, runs: &mut Vec<(String, Format)>) {
+    let mut plain_start = 0;
+    let mut i = 0;
+    while let Some(rest) = text.get(i..).filter(|rest| !rest.is_empty()) {
+        let found = DELIMITERS.iter().find_map(|delimiter| {
+            let inner = rest.strip_prefix(delimiter)?;
+            let end = closing(inner, delimiter)?;
+            Some((*delimiter, &inner[..end]))
+        });
+        let Some((delimiter, inner)) = found else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+            continue;
+        };
+        push(runs, &text[plain_start..i], format);
+        if delimiter == "`" {
+            // NOTE: nothing is formatted inside inline code
+            push(runs, inner, format.with(delimiter));
+        } else {
+            inline(inner, format.with(delimiter), runs);
+        }
+        i += delimiter.len() * 2 + inner.len();
+        plain_start = i;
+    }
+    push(runs, &text[plain_start..], format);
+}
+
+/// Where the delimiter closing the formatted text is. The text can't start or end with
+/// whitespace, so that e.g. "2 * 3 * 4" is left as it is
+fn closing(inner: &str, delimiter: &str) -> Option<usize> {
+    if inner.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut i = 0;
+    while let Some(rest) = inner.get(i..).filter(|rest| !rest.is_empty()) {
+        if delimiter == "*" && rest.starts_with("**") {
+            i += 2;
+            continue;
+        }
+        if i > 0 && rest.starts_with(delimiter) && !inner[..i].ends_with(char::is_whitespace) {
+            return Some(i);
+        }
+        i += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(delimiters: &[&str]) -> Format {
+        delimiters
+            .iter()
+            .fold(Format::default(), |format, delimiter| {
+                format.with(delimiter)
+            })
+    }
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(
+            parse("a **bold *and italic* too** `x * y` ~~gone~~"),
+            vec![
+                ("a ".to_string(), Format::default()),
+                ("bold ".to_string(), format(&["**"])),
+                ("and italic".to_string(), format(&["**", "*"])),
+                (" too".to_string(), format(&["**"])),
+                (" ".to_string(), Format::default()),
+                ("x * y".to_string(), format(&["`"])),
+                (" ".to_string(), Format::default()),
+                ("gone".to_string(), format(&["~~"])),
+            ]
+        );
+        // Unclosed and spaced out delimiters are left as they are
+        assert_eq!(
+            parse("2 * 3 * 4 and **open"),
+            vec![("2 * 3 * 4 and **open".to_string(), Format::default())]
+        );
+    }
+
+    #[test]
+    fn test_code_block() {
+        // Whitespace in the block is kept as it is
+        assert_eq!(
+            parse("look:\n```rust\nfn main() {\n    x  =  1;\n}\n```\ndone"),
+            vec![
+                ("look:\n".to_string(), Format::default()),
+                (
+                    "fn main() {\n    x  =  1;\n}".to_string(),
+                    Format {
+                        code_block: true,
+                        ..Format::default()
+                    }
+                ),
+                ("\ndone".to_string(), Format::default()),
+            ]
+        );
+    }
+}
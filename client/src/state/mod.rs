@@ -1,4 +1,7 @@
 pub(crate) mod action;
 pub(crate) mod chat;
+pub(crate) mod markdown;
+pub(crate) mod spill;
+#[allow(clippy::module_inception)]
 pub(crate) mod state;
 pub(crate) mod state_manager;
@@ -0,0 +1,75 @@
+use std::fs::{self, File};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use shared::parse_async::Frame;
+
+use crate::state::chat::ChatMessage;
+
+// Tells apart the spill files of the chat logs of a single process
+static NEXT_SPILL: AtomicU64 = AtomicU64::new(0);
+
+/// Messages that no longer fit in the chat log, kept in a local file so that they can
+/// still be scrolled back to. The file only lives as long as the spill
+pub(crate) struct Spill {
+    file: File,
+    path: PathBuf,
+    // Where each message starts in the file, the oldest first
+    offsets: Vec<u64>,
+    end: u64,
+}
+
+impl Spill {
+    pub(crate) fn create(dir: &Path) -> Result<Self> {
+        let path = dir.join(format!(
+            "chat-{}-{}.spill",
+            std::process::id(),
+            NEXT_SPILL.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(Self {
+            file,
+            path,
+            offsets: Vec::new(),
+            end: 0,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub(crate) fn push(&mut self, message: &ChatMessage) -> Result<()> {
+        let mut encoded = Vec::new();
+        message.to_frame().encode(&mut encoded);
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&encoded)?;
+        self.offsets.push(self.end);
+        self.end += encoded.len() as u64;
+        Ok(())
+    }
+
+    /// The message at the index, counted from the oldest one
+    pub(crate) fn get(&mut self, index: usize) -> Result<ChatMessage> {
+        let start = self.offsets[index];
+        let end = self.offsets.get(index + 1).copied().unwrap_or(self.end);
+        let mut encoded = vec![0; (end - start) as usize];
+        self.file.seek(SeekFrom::Start(start))?;
+        self.file.read_exact(&mut encoded)?;
+        ChatMessage::from_frame(Frame::parse(&mut Cursor::new(&encoded[..]))?)
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        // NOTE: best effort, the file is in a cache directory anyway
+        let _ = fs::remove_file(&self.path);
+    }
+}
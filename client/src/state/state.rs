@@ -1,17 +1,42 @@
-use crate::state::chat::{ChatLog, ChatMessage, SYSTEM_ICON, USER_ICON};
-use shared::message::Message;
-use std::collections::HashSet;
+use crate::state::chat::{ChatLog, ChatMessage, DIRECT_ICON, SYSTEM_ICON, USER_ICON};
+use chrono::TimeZone;
+use shared::message::{Chatter, Message, NoticeLevel, Presence};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::time::{Duration, Instant};
 
-#[derive(Clone)]
+// How long a "typing" notification stays visible without being refreshed
+const TYPING_EXPIRY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Default)]
 pub(crate) enum ConnectionStatus {
+    #[default]
     Offline,
     Online,
 }
 
-impl Default for ConnectionStatus {
-    fn default() -> Self {
-        Self::Offline
-    }
+#[derive(Clone)]
+pub(crate) struct OnlineUser {
+    pub(crate) presence: Presence,
+    pub(crate) status_text: String,
+    pub(crate) joined_at: chrono::DateTime<chrono::Local>,
+    pub(crate) messages_sent: u64,
+}
+
+fn decode_chatter(chatter: Chatter) -> (String, OnlineUser) {
+    (
+        String::from_utf8(chatter.name.to_vec())
+            .expect("Failed decoding bytes while parsig online users"),
+        OnlineUser {
+            presence: chatter.presence,
+            status_text: String::from_utf8(chatter.status_text.to_vec())
+                .expect("Couldn't decode the status in utf8"),
+            joined_at: chrono::Local
+                .timestamp_millis_opt(chatter.joined_at as i64)
+                .single()
+                .expect("Join time out of range"),
+            messages_sent: chatter.messages_sent,
+        },
+    )
 }
 
 #[derive(Default, Clone)]
@@ -19,11 +44,28 @@ pub(crate) struct State {
     pub(crate) login_name: Option<String>,
     // TODO: String for simplicity, ideally should be: {user, msg, time, icon}
     pub(crate) chat_messages: ChatLog,
-    // TODO: Same as above, ideally {name, icon, ?time_joined}
-    pub(crate) online_users: HashSet<String>,
+    pub(crate) online_users: BTreeMap<String, OnlineUser>,
     pub(crate) connection_status: ConnectionStatus,
     pub(crate) messages_sent: u64,
     pub(crate) timer: f64,
+    // (message id, emoji) pairs this user reacted with, used to toggle reactions off
+    pub(crate) own_reactions: HashSet<(u64, String)>,
+    // Other users currently typing, along with the last time they were seen typing
+    pub(crate) typing_users: HashMap<String, Instant>,
+    pub(crate) presence: Presence,
+    pub(crate) status_text: String,
+    // Last message id this user had seen according to the server, as of logging in
+    pub(crate) read_marker: Option<u64>,
+    // Id of the newest message shown, synced back to the server as the read marker
+    pub(crate) last_seen_id: u64,
+    // Messages @mentioning this user since they last sent a message
+    pub(crate) unread_mentions: u64,
+    // Why the server closed the connection, shown on the login page
+    pub(crate) disconnect_reason: Option<String>,
+    // Rooms joined on top of the main chat
+    pub(crate) rooms: BTreeSet<String>,
+    // Room the messages typed go to, the main chat if none
+    pub(crate) current_room: Option<String>,
 }
 
 // TODO: perhaps it makes sense to return a Result from here
@@ -33,15 +75,78 @@ impl State {
     pub(crate) fn handle_server_message(&mut self, server_message: Message) {
         match server_message {
             Message::ChatMessage(m) => {
+                let author = std::str::from_utf8(&m.name).unwrap_or_default();
+                self.typing_users.remove(author);
+                // NOTE: the roster only carries stats as of the last snapshot
+                if let Some(user) = self.online_users.get_mut(author) {
+                    user.messages_sent += 1;
+                }
+                self.put_chat_message(m);
+            }
+            Message::DirectMessage(m) => {
+                let from =
+                    String::from_utf8(m.from.to_vec()).expect("Couldn't decode the name in utf8");
+                let to =
+                    String::from_utf8(m.to.to_vec()).expect("Couldn't decode the name in utf8");
+                let sent_at = String::from_utf8(m.sent_at.to_vec())
+                    .expect("Couldn't decode the time in utf8");
+                // NOTE: the date is only worth showing for messages that waited since another day
+                let today = chrono::Local::now().format("%Y-%m-%d ").to_string();
+                let sent_at = match sent_at.strip_prefix(&today) {
+                    Some(time) => time.to_string(),
+                    None => sent_at,
+                };
                 let chat_message = ChatMessage::new(
-                    String::from_utf8(m.name.to_vec()).expect("Couldn't decode the name in utf8"),
-                    String::from_utf8(m.sent_at.to_vec())
-                        .expect("Couldn't decode the time in utf8"),
+                    format!("{from} → {to}"),
+                    sent_at,
                     String::from_utf8(m.msg.to_vec()).expect("Couldn't decode the message in utf8"),
-                    USER_ICON.to_string(),
+                    DIRECT_ICON.to_string(),
                 );
                 self.chat_messages.put_message(chat_message);
             }
+            Message::Notice(m) => {
+                let author = match m.level {
+                    NoticeLevel::Info => "System",
+                    NoticeLevel::Error => "Error",
+                };
+                let msg =
+                    String::from_utf8(m.msg.to_vec()).expect("Couldn't decode the message in utf8");
+                // NOTE: command replies can span several lines
+                for line in msg.lines() {
+                    let chat_message = ChatMessage::new(
+                        author.to_string(),
+                        "".to_string(),
+                        line.to_string(),
+                        SYSTEM_ICON.to_string(),
+                    );
+                    self.chat_messages.put_message(chat_message);
+                }
+            }
+            Message::Disconnect(m) => {
+                self.disconnect_reason = Some(
+                    String::from_utf8(m.reason.to_vec())
+                        .expect("Couldn't decode the reason in utf8"),
+                );
+            }
+            Message::ReadMarker(m) => {
+                self.read_marker = Some(m.message_id);
+            }
+            Message::History(m) => {
+                // NOTE: the history comes again after missing broadcasts, the divider is only
+                // placed on login
+                let logging_in = self.chat_messages.last_message_id().is_none();
+                let first_unread = m
+                    .messages
+                    .iter()
+                    .map(|m| m.id)
+                    .find(|id| self.read_marker.is_some_and(|marker| *id > marker));
+                m.messages
+                    .into_iter()
+                    .for_each(|m| self.put_chat_message(m));
+                if logging_in {
+                    self.chat_messages.set_new_messages_from(first_unread);
+                }
+            }
             Message::WelcomeMessage(m) => {
                 let msg =
                     String::from_utf8(m.msg.to_vec()).expect("Couldn't decode the message in utf8");
@@ -74,21 +179,109 @@ impl State {
                 self.chat_messages.put_message(chat_message);
             }
             Message::WhoIsInChat(m) => {
-                self.online_users = m
-                    .chatters
-                    .iter()
-                    .map(|c| {
-                        String::from_utf8(c.to_vec())
-                            .expect("Failed decoding bytes while parsig online users")
+                self.online_users = m.chatters.into_iter().map(decode_chatter).collect();
+                self.sync_own_status();
+            }
+            Message::RosterAdd(m) => {
+                let (name, user) = decode_chatter(m.chatter);
+                self.online_users.insert(name, user);
+                self.sync_own_status();
+            }
+            Message::RosterRemove(m) => {
+                self.online_users
+                    .remove(std::str::from_utf8(&m.name).unwrap_or_default());
+            }
+            Message::ReactionsUpdated(m) => {
+                let reactions = m
+                    .reactions
+                    .into_iter()
+                    .map(|(emoji, count)| {
+                        (
+                            String::from_utf8(emoji.to_vec())
+                                .expect("Couldn't decode the emoji in utf8"),
+                            count,
+                        )
                     })
                     .collect();
+                self.chat_messages.set_reactions(m.message_id, reactions);
             }
-            Message::Login(_) | Message::Logout(_) => {
+            Message::Typing(m) => {
+                let name =
+                    String::from_utf8(m.name.to_vec()).expect("Couldn't decode the name in utf8");
+                if self.login_name.as_ref() != Some(&name) {
+                    self.typing_users.insert(name, Instant::now());
+                }
+            }
+            Message::Login(_)
+            | Message::Logout(_)
+            | Message::Reaction(_)
+            | Message::SetStatus(_)
+            | Message::JoinRoom(_)
+            | Message::PartRoom(_) => {
                 unreachable!("Client must not receive server-side events")
             }
         }
     }
+    /// Takes the user's own presence from the roster, the server changes it on its own
+    /// after a while of inactivity
+    fn sync_own_status(&mut self) {
+        let Some(own) = self
+            .login_name
+            .as_ref()
+            .and_then(|name| self.online_users.get(name))
+        else {
+            return;
+        };
+        self.presence = own.presence;
+        self.status_text = own.status_text.clone();
+    }
+
+    pub(crate) fn put_system_message(&mut self, msg: String) {
+        let chat_message = ChatMessage::new(
+            "System".to_string(),
+            "".to_string(),
+            msg,
+            SYSTEM_ICON.to_string(),
+        );
+        self.chat_messages.put_message(chat_message);
+    }
+
+    fn put_chat_message(&mut self, m: shared::message::ChatMessage) {
+        // NOTE: a resync after missing broadcasts, or the history after changing the nick,
+        // overlaps with what is already shown, spilled or not
+        let room = std::str::from_utf8(&m.room).unwrap_or_default();
+        if !self.chat_messages.see(room, m.id) {
+            return;
+        }
+        self.last_seen_id = self.last_seen_id.max(m.id);
+        let mentions_me = self
+            .login_name
+            .as_ref()
+            .is_some_and(|name| m.mentions(name) && m.name != name.as_bytes());
+        // NOTE: mentions from the history only count if they weren't read in a previous session
+        if mentions_me && self.read_marker.is_none_or(|marker| m.id > marker) {
+            self.unread_mentions += 1;
+        }
+        let name = String::from_utf8(m.name.to_vec()).expect("Couldn't decode the name in utf8");
+        // NOTE: room messages are authored "name → room", like direct messages
+        let author = match room {
+            "" => name,
+            room => format!("{name} → {room}"),
+        };
+        let chat_message = ChatMessage::new(
+            author,
+            String::from_utf8(m.sent_at.to_vec()).expect("Couldn't decode the time in utf8"),
+            String::from_utf8(m.msg.to_vec()).expect("Couldn't decode the message in utf8"),
+            USER_ICON.to_string(),
+        )
+        .with_id(m.id)
+        .with_mention(mentions_me);
+        self.chat_messages.put_message(chat_message);
+    }
+
     pub(crate) fn tick_timer(&mut self, tick: f64) {
         self.timer += tick;
+        self.typing_users
+            .retain(|_, last_typed| last_typed.elapsed() < TYPING_EXPIRY);
     }
 }
@@ -2,9 +2,12 @@ use std::time::Duration;
 
 use crate::state::state::ConnectionStatus;
 use crate::state::{action::Action, state::State};
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bytes::Bytes;
-use shared::message::Message;
+use shared::message::{
+    DirectMessage, JoinRoom, Login, Logout, Message, PartRoom, Reaction, ReactionKind, ReadMarker,
+    SetStatus, Typing,
+};
 use shared::{connection::Connection, message::ChatMessage};
 use tokio::net::TcpStream;
 use tokio::sync::broadcast;
@@ -15,6 +18,9 @@ use tokio::{
     time::interval,
 };
 
+// Password of the name logged in with, for names that have one set with /password
+const PASSWORD_ENV: &str = "CHAD_PASSWORD";
+
 pub(crate) struct StateManager {
     state_tx: UnboundedSender<State>,
 }
@@ -32,6 +38,8 @@ impl StateManager {
         let mut connection: Option<Connection<OwnedWriteHalf, OwnedReadHalf>> = None;
         let mut state = State::default();
         let mut ticker = interval(Duration::from_millis(500));
+        // Read marker last reported to the server, it is synced on ticks to batch updates
+        let mut synced_read_marker = 0;
         self.state_tx.send(state.clone())?;
 
         loop {
@@ -48,6 +56,8 @@ impl StateManager {
                                 if maybe_io_error.is_some() && maybe_io_error.unwrap().kind() == std::io::ErrorKind::ConnectionReset {
                                     connection = None;
                                     state.connection_status = ConnectionStatus::Offline;
+                                    // NOTE: the server usually says why, unless it went away abruptly
+                                    state.disconnect_reason.get_or_insert_with(|| "Lost connection to the server".to_string());
                                 }
                             }
                         }
@@ -56,41 +66,177 @@ impl StateManager {
                         Action::ConnectAndLogin { .. } => unreachable!("Impossible action when the connection is already established"),
                         Action::SendMessage { message } => {
                             conn.write_frame(
-                                shared::message::Message::ChatMessage(
-                                    ChatMessage::new(
+                                Message::ChatMessage(chat_message(&state, message)).into_frame()
+                            ).await?;
+                            state.messages_sent += 1;
+                            // NOTE: replying in the chat counts as having caught up on mentions
+                            state.unread_mentions = 0;
+                        },
+                        Action::Emote { action } => {
+                            // NOTE: emotes travel as plain chat text, clients render the "/me" prefix
+                            conn.write_frame(
+                                Message::ChatMessage(chat_message(&state, format!("/me {action}"))).into_frame()
+                            ).await?;
+                            state.messages_sent += 1;
+                            state.unread_mentions = 0;
+                        },
+                        Action::ChangeNick { name } => {
+                            // NOTE: the server knows clients by name, so a new name means a new session,
+                            // the current one is kept until the server takes the new one
+                            let (new_connection, welcome) = match connect_and_login(&name).await {
+                                Ok(login) => login,
+                                Err(e) => {
+                                    state.put_system_message(format!("Couldn't change the nick to {name}: {e}"));
+                                    self.state_tx.send(state.clone())?;
+                                    continue;
+                                }
+                            };
+                            // NOTE: the old session is over either way, a broken connection doesn't matter
+                            let _ = conn.write_frame(
+                                Message::Logout(
+                                    Logout::new(state.login_name.clone().expect("Empty login name").into())
+                                ).into_frame()
+                            ).await;
+                            *conn = new_connection;
+                            // NOTE: the messages shown so far stay, the new session's history is
+                            // deduplicated against them
+                            state = State {
+                                login_name: Some(name),
+                                connection_status: ConnectionStatus::Online,
+                                chat_messages: std::mem::take(&mut state.chat_messages),
+                                ..State::default()
+                            };
+                            state.handle_server_message(welcome);
+                            synced_read_marker = 0;
+                        },
+                        Action::ShowSystemMessage { message } => {
+                            message.lines().for_each(|line| state.put_system_message(line.to_string()));
+                        },
+                        Action::SendDirectMessage { to, message } => {
+                            conn.write_frame(
+                                Message::DirectMessage(
+                                    DirectMessage::new(
                                         state.login_name.clone().expect("Empty login name").into(),
+                                        to.into(),
                                         chrono::Local::now(),
                                         message.into(),
                                     )
                                 ).into_frame()
                             ).await?;
-                            state.messages_sent += 1;
+                        },
+                        Action::React { message_id, emoji } => {
+                            let kind = if state.own_reactions.remove(&(message_id, emoji.clone())) {
+                                ReactionKind::Remove
+                            } else {
+                                state.own_reactions.insert((message_id, emoji.clone()));
+                                ReactionKind::Add
+                            };
+                            conn.write_frame(
+                                Message::Reaction(
+                                    Reaction::new(
+                                        message_id,
+                                        state.login_name.clone().expect("Empty login name").into(),
+                                        emoji.into(),
+                                        kind,
+                                    )
+                                ).into_frame()
+                            ).await?;
+                        },
+                        Action::Typing => {
+                            conn.write_frame(
+                                Message::Typing(
+                                    Typing::new(state.login_name.clone().expect("Empty login name").into())
+                                ).into_frame()
+                            ).await?;
+                        },
+                        Action::SetStatus { presence, status_text } => {
+                            conn.write_frame(
+                                Message::SetStatus(
+                                    SetStatus::new(presence, status_text.clone().into())
+                                ).into_frame()
+                            ).await?;
+                            state.presence = presence;
+                            state.status_text = status_text;
+                        },
+                        Action::JoinRoom { room: None } => {
+                            state.current_room = None;
+                            state.put_system_message("Talking in the main chat".to_string());
+                        },
+                        Action::JoinRoom { room: Some(room) } => {
+                            // NOTE: joining a room again only switches to it
+                            if state.rooms.insert(room.clone()) {
+                                conn.write_frame(
+                                    Message::JoinRoom(JoinRoom::new(room.clone().into())).into_frame()
+                                ).await?;
+                            }
+                            state.put_system_message(format!("Talking in {room}, /join alone goes back to the main chat"));
+                            state.current_room = Some(room);
+                        },
+                        Action::PartRoom { room } => {
+                            let Some(room) = room.or_else(|| state.current_room.clone()) else {
+                                state.put_system_message("The main chat can't be left, try /quit".to_string());
+                                self.state_tx.send(state.clone())?;
+                                continue;
+                            };
+                            if !state.rooms.remove(&room) {
+                                state.put_system_message(format!("You are not in {room}"));
+                                self.state_tx.send(state.clone())?;
+                                continue;
+                            }
+                            conn.write_frame(
+                                Message::PartRoom(PartRoom::new(room.clone().into())).into_frame()
+                            ).await?;
+                            if state.current_room.as_ref() == Some(&room) {
+                                state.current_room = None;
+                            }
                         },
                         Action::Quit => {
                             let _ = termination_tx.send(());
                             break;
                         },
                     },
-                    _ = ticker.tick() => state.tick_timer(0.5),
+                    _ = ticker.tick() => {
+                        state.tick_timer(0.5);
+                        if state.last_seen_id > synced_read_marker {
+                            conn.write_frame(
+                                Message::ReadMarker(ReadMarker::new(state.last_seen_id)).into_frame()
+                            ).await?;
+                            synced_read_marker = state.last_seen_id;
+                        }
+                    },
                 }
             } else {
                 select! {
                     Some(ui_event) = action_rx.recv() => match ui_event {
-                        Action::ConnectAndLogin { name } => {
-                            connection = Some(create_connection_handle("127.0.0.1:8080").await?);
-                            let login_message = shared::message::Message::Login(
-                                shared::message::Login::new(
-                                    Bytes::copy_from_slice(name.as_bytes())
-                                )
-                            );
-                            connection
-                                .as_mut()
-                                .expect("Connection suddenly closed shortly after opening")
-                                .write_frame(login_message.into_frame()).await?;
-                            state.login_name = Some(name);
-                            state.connection_status = ConnectionStatus::Online
+                        Action::ConnectAndLogin { name } => match connect_and_login(&name).await {
+                            Ok((new_connection, welcome)) => {
+                                connection = Some(new_connection);
+                                // NOTE: everything from a previous session comes back from the server
+                                state = State {
+                                    login_name: Some(name),
+                                    connection_status: ConnectionStatus::Online,
+                                    ..State::default()
+                                };
+                                state.handle_server_message(welcome);
+                                synced_read_marker = 0;
+                            }
+                            // NOTE: the login page shows why, the user may try again
+                            Err(e) => state.disconnect_reason = Some(format!("Couldn't log in: {e}")),
+                        },
+                        Action::ShowSystemMessage { message } => {
+                            message.lines().for_each(|line| state.put_system_message(line.to_string()));
                         },
-                        Action::SendMessage { .. } => unreachable!("Broken state: requesting to send a message when the client if offline"),
+                        // NOTE: a keystroke may race with the connection going down, nobody to notify then
+                        Action::Typing => {},
+                        // NOTE: the connection may go down while the chat page is still up
+                        Action::SendMessage { .. }
+                        | Action::SendDirectMessage { .. }
+                        | Action::Emote { .. }
+                        | Action::ChangeNick { .. }
+                        | Action::React { .. }
+                        | Action::SetStatus { .. }
+                        | Action::JoinRoom { .. }
+                        | Action::PartRoom { .. } => state.put_system_message("Not connected".to_string()),
                         Action::Quit => break,
                     },
                     _ = ticker.tick() => {},
@@ -102,6 +248,39 @@ impl StateManager {
     }
 }
 
+/// A chat message to the room the user is talking in
+fn chat_message(state: &State, text: String) -> ChatMessage {
+    let mut message = ChatMessage::new(
+        state.login_name.clone().expect("Empty login name").into(),
+        chrono::Local::now(),
+        text.into(),
+    );
+    if let Some(room) = &state.current_room {
+        message.room = room.clone().into();
+    }
+    message
+}
+
+/// Logs in and waits for the server to take the client, the welcome is handed back for the
+/// state to show
+async fn connect_and_login(
+    name: &str,
+) -> Result<(Connection<OwnedWriteHalf, OwnedReadHalf>, Message)> {
+    let mut connection = create_connection_handle("127.0.0.1:8080").await?;
+    // NOTE: the same password is sent for any name, names without one don't need it
+    let password = std::env::var(PASSWORD_ENV).unwrap_or_default();
+    let login_message = Message::Login(Login::new(
+        Bytes::copy_from_slice(name.as_bytes()),
+        password.into(),
+    ));
+    connection.write_frame(login_message.into_frame()).await?;
+    match Message::from_frame(connection.read_frame().await?)? {
+        welcome @ Message::WelcomeMessage(_) => Ok((connection, welcome)),
+        Message::Disconnect(m) => bail!("{}", String::from_utf8_lossy(&m.reason)),
+        unexpected => bail!("Expected a welcome, got {:?}", unexpected),
+    }
+}
+
 async fn create_connection_handle(addr: &str) -> Result<Connection<OwnedWriteHalf, OwnedReadHalf>> {
     let stream = TcpStream::connect(addr).await?;
     let (read_half, write_half) = stream.into_split();
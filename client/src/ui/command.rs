@@ -0,0 +1,204 @@
+use shared::message::{is_room_name, Presence};
+
+use crate::state::action::Action;
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    help: &'static str,
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        usage: "/help",
+        help: "list the available commands",
+    },
+    Command {
+        name: "msg",
+        usage: "/msg <name> <text>",
+        help: "send a direct message",
+    },
+    Command {
+        name: "me",
+        usage: "/me <action>",
+        help: "describe what you are doing",
+    },
+    Command {
+        name: "nick",
+        usage: "/nick <name>",
+        help: "log in again under another name",
+    },
+    Command {
+        name: "away",
+        usage: "/away [status]",
+        help: "go away, optionally saying why",
+    },
+    Command {
+        name: "back",
+        usage: "/back",
+        help: "come back online",
+    },
+    Command {
+        name: "join",
+        usage: "/join [#room]",
+        help: "talk in a room, or in the main chat without one",
+    },
+    Command {
+        name: "part",
+        usage: "/part [#room]",
+        help: "leave a room",
+    },
+    Command {
+        name: "quit",
+        usage: "/quit",
+        help: "leave the chat",
+    },
+];
+
+// Commands the server handles, typed lines that start with them are sent as is
+// NOTE: keep in sync with the server's builtins, "/commands" lists what it actually knows
+const SERVER_COMMANDS: &[&str] = &[
+    "commands", "who", "uptime", "stats", "password", "kick", "mute", "unmute", "ban", "unban",
+    "role",
+];
+
+/// Names of the commands, the client's and the server's
+pub(crate) fn names() -> impl Iterator<Item = &'static str> {
+    COMMANDS
+        .iter()
+        .map(|c| c.name)
+        .chain(SERVER_COMMANDS.iter().copied())
+}
+
+/// What a line typed into the chat input stands for
+#[derive(Debug, PartialEq)]
+pub(crate) enum Input {
+    Text(String),
+    Command(Action),
+}
+
+/// Turns the chat input into either text to send or a command, an error is a usage message
+/// meant for the user
+pub(crate) fn parse(source: &str) -> Result<Input, String> {
+    let Some(command_line) = source.strip_prefix('/') else {
+        return Ok(Input::Text(source.to_string()));
+    };
+    // NOTE: "//text" is sent as is, the server unescapes it into "/text"
+    if command_line.starts_with('/') {
+        return Ok(Input::Text(source.to_string()));
+    }
+    let (name, args) = command_line
+        .split_once(char::is_whitespace)
+        .unwrap_or((command_line, ""));
+    let args = args.trim();
+    // NOTE: anything not known locally may be a server command
+    let Some(command) = COMMANDS.iter().find(|c| c.name == name) else {
+        return Ok(Input::Text(source.to_string()));
+    };
+    let usage = || format!("Usage: {}", command.usage);
+
+    let action = match command.name {
+        "help" => Action::ShowSystemMessage { message: help() },
+        "msg" => {
+            let (to, message) = args.split_once(char::is_whitespace).ok_or_else(usage)?;
+            Action::SendDirectMessage {
+                to: to.to_string(),
+                message: message.trim().to_string(),
+            }
+        }
+        "me" if !args.is_empty() => Action::Emote {
+            action: args.to_string(),
+        },
+        "nick" if !args.is_empty() && !args.contains(char::is_whitespace) => Action::ChangeNick {
+            name: args.to_string(),
+        },
+        "away" => Action::SetStatus {
+            presence: Presence::Away,
+            status_text: args.to_string(),
+        },
+        "back" if args.is_empty() => Action::SetStatus {
+            presence: Presence::Online,
+            status_text: String::new(),
+        },
+        "join" | "part" => {
+            if !args.is_empty() && !is_room_name(args) {
+                return Err(usage());
+            }
+            let room = Some(args.to_string()).filter(|room| !room.is_empty());
+            match command.name {
+                "join" => Action::JoinRoom { room },
+                _ => Action::PartRoom { room },
+            }
+        }
+        "quit" if args.is_empty() => Action::Quit,
+        _ => return Err(usage()),
+    };
+    Ok(Input::Command(action))
+}
+
+// Keys that aren't obvious from the input, listed along with the commands
+const KEYS: &[(&str, &str)] = &[
+    ("Ctrl-F", "search the chat"),
+    (
+        "/",
+        "search the chat, while scrolled up with an empty input",
+    ),
+    (
+        "n, N",
+        "older and newer match while searching, other keys close",
+    ),
+    ("Ctrl-R", "search what you sent"),
+];
+
+fn help() -> String {
+    COMMANDS
+        .iter()
+        .map(|c| format!("{:<20} {}", c.usage, c.help))
+        .chain(std::iter::once(format!(
+            "{:<20} {}",
+            "/commands", "list the commands the server knows"
+        )))
+        .chain(
+            KEYS.iter()
+                .map(|(key, help)| format!("{:<20} {}", key, help)),
+        )
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse("hello"), Ok(Input::Text("hello".to_string())));
+        assert_eq!(parse("//me"), Ok(Input::Text("//me".to_string())));
+        assert_eq!(
+            parse("/msg bob  see you"),
+            Ok(Input::Command(Action::SendDirectMessage {
+                to: "bob".to_string(),
+                message: "see you".to_string(),
+            }))
+        );
+        assert_eq!(
+            parse("/msg bob"),
+            Err("Usage: /msg <name> <text>".to_string())
+        );
+        assert_eq!(parse("/nick"), Err("Usage: /nick <name>".to_string()));
+        assert_eq!(parse("/quit"), Ok(Input::Command(Action::Quit)));
+        assert_eq!(parse("/who"), Ok(Input::Text("/who".to_string())));
+        assert_eq!(
+            parse("/join #rust"),
+            Ok(Input::Command(Action::JoinRoom {
+                room: Some("#rust".to_string())
+            }))
+        );
+        assert_eq!(parse("/join rust"), Err("Usage: /join [#room]".to_string()));
+        assert_eq!(
+            parse("/part"),
+            Ok(Input::Command(Action::PartRoom { room: None }))
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use crate::ui::command;
+
+/// Candidates cycled through with Tab, for the word before the cursor
+pub(crate) struct Completion {
+    // Where the completed word starts in the input
+    pub(crate) start: usize,
+    pub(crate) candidates: Vec<String>,
+    pub(crate) selected: usize,
+}
+
+impl Completion {
+    pub(crate) fn selected(&self) -> &str {
+        &self.candidates[self.selected]
+    }
+
+    pub(crate) fn next(&mut self) {
+        self.selected = (self.selected + 1) % self.candidates.len();
+    }
+
+    pub(crate) fn previous(&mut self) {
+        self.selected = (self.selected + self.candidates.len() - 1) % self.candidates.len();
+    }
+}
+
+fn starts_with_ignore_case(candidate: &str, prefix: &str) -> bool {
+    candidate.to_lowercase().starts_with(&prefix.to_lowercase())
+}
+
+/// Completions of the word, `preceding` is the text before it. "@" completes nicks, "#"
+/// rooms and "/" commands at the start of the line. The names taken by /msg and the
+/// moderation commands, and the rooms of /join and /part, are completed without the sigil
+pub(crate) fn candidates<'a>(
+    preceding: &str,
+    word: &str,
+    nicks: impl IntoIterator<Item = &'a str>,
+    rooms: impl IntoIterator<Item = &'a str>,
+) -> Vec<String> {
+    let complete = |names: Vec<&str>, prefix: &str, sigil: &str| -> Vec<String> {
+        names
+            .into_iter()
+            .filter(|name| starts_with_ignore_case(name, prefix))
+            .map(|name| format!("{sigil}{name}"))
+            .collect()
+    };
+    let nicks = nicks.into_iter().collect();
+    let rooms = rooms.into_iter().collect();
+    let mut found = match (preceding.trim_end(), word) {
+        (_, word) if word.starts_with('@') => complete(nicks, &word[1..], "@"),
+        (_, word) if word.starts_with('#') => complete(rooms, &word[1..], "#"),
+        ("", word) if word.starts_with('/') => {
+            complete(command::names().collect(), &word[1..], "/")
+        }
+        ("/msg" | "/kick" | "/mute" | "/unmute" | "/ban" | "/role", word) => {
+            complete(nicks, word, "")
+        }
+        ("/join" | "/part", word) => complete(rooms, word, "#"),
+        _ => Vec::new(),
+    };
+    found.sort();
+    found.dedup();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidates() {
+        let nicks = ["bob", "Bobby", "alice"];
+        let rooms = ["general", "games"];
+        assert_eq!(
+            candidates("hi ", "@bo", nicks, rooms),
+            vec!["@Bobby", "@bob"]
+        );
+        assert_eq!(candidates("", "/ms", nicks, rooms), vec!["/msg"]);
+        assert_eq!(
+            candidates("", "/un", nicks, rooms),
+            vec!["/unban", "/unmute"]
+        );
+        assert_eq!(candidates("/kick ", "al", nicks, rooms), vec!["alice"]);
+        assert_eq!(candidates("/msg ", "", nicks, rooms).len(), 3);
+        assert_eq!(candidates("/join ", "ga", nicks, rooms), vec!["#games"]);
+        // Commands only start a line, and plain words are not completed
+        assert!(candidates("hi ", "/ms", nicks, rooms).is_empty());
+        assert!(candidates("hi ", "bo", nicks, rooms).is_empty());
+    }
+}
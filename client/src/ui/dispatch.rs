@@ -5,10 +5,12 @@ use crate::{
     },
     ui::page::widget::Widget,
 };
-use crossterm::event::KeyEvent;
+use crossterm::event::{KeyCode, KeyEvent, MouseEvent};
+use ratatui::widgets::Block;
 use tokio::sync::mpsc::UnboundedSender;
 
 use super::page::{chat_page::ChatPage, login_page::LoginPage};
+use super::theme::{Theme, ThemeConfig};
 
 enum ActivePage {
     Login,
@@ -19,14 +21,22 @@ pub(crate) struct Dispatcher {
     active_page: ActivePage,
     login_page: LoginPage,
     chat_page: ChatPage,
+    theme_config: ThemeConfig,
+    theme: Theme,
 }
 
 impl Dispatcher {
-    pub(crate) fn new(action_tx: UnboundedSender<Action>, state: State) -> Self {
+    pub(crate) fn new(
+        action_tx: UnboundedSender<Action>,
+        state: State,
+        theme_config: ThemeConfig,
+    ) -> Self {
         Self {
             active_page: ActivePage::Login,
             login_page: LoginPage::new(action_tx.clone()),
             chat_page: ChatPage::new(action_tx, state),
+            theme: theme_config.theme(),
+            theme_config,
         }
     }
 
@@ -45,9 +55,18 @@ impl Dispatcher {
     }
 
     pub(crate) fn handle_key_event(&mut self, key: KeyEvent) {
+        // NOTE: F4 switches between the theme presets on every page
+        if key.code == KeyCode::F(4) {
+            self.theme = self.theme_config.theme_for(self.theme.preset.next());
+            return;
+        }
         self.get_active_page_mut().handle_key_event(key);
     }
 
+    pub(crate) fn handle_mouse_event(&mut self, mouse: MouseEvent) {
+        self.get_active_page_mut().handle_mouse_event(mouse);
+    }
+
     pub(crate) fn update(&mut self, state: State) {
         self.active_page = match state.connection_status {
             ConnectionStatus::Offline => ActivePage::Login,
@@ -57,6 +76,7 @@ impl Dispatcher {
     }
 
     pub(crate) fn render(&self, frame: &mut ratatui::prelude::Frame) {
-        self.get_active_page().render(frame);
+        frame.render_widget(Block::default().style(self.theme.base()), frame.size());
+        self.get_active_page().render(frame, &self.theme);
     }
 }
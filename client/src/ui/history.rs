@@ -0,0 +1,195 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+// Entries kept across sessions, the oldest ones are forgotten first
+const HISTORY_CAPACITY: usize = 1000;
+
+/// Lines sent from the input, recalled with Up/Down and searched with Ctrl-R
+pub(crate) struct InputHistory {
+    entries: Vec<String>,
+    // Where the history is persisted, `None` if there is no data directory
+    path: Option<PathBuf>,
+    // Entry being recalled, `None` while editing a fresh line
+    position: Option<usize>,
+    // The fresh line, given back when moving past the newest entry
+    draft: String,
+}
+
+/// One entry per line, with newlines and backslashes escaped
+fn encode(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn decode(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                entry.push('\\');
+                chars.next();
+            }
+            (c, _) => entry.push(c),
+        }
+    }
+    entry
+}
+
+impl InputHistory {
+    /// Reads the history of previous sessions from the user's data directory
+    pub(crate) fn load() -> Self {
+        let path = dirs::data_dir().map(|dir| dir.join("chad").join("history"));
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(decode).collect())
+            .unwrap_or_default();
+        let mut history = Self {
+            entries,
+            path,
+            position: None,
+            draft: String::new(),
+        };
+        if history.entries.len() > HISTORY_CAPACITY {
+            history
+                .entries
+                .drain(..history.entries.len() - HISTORY_CAPACITY);
+            history.rewrite();
+        }
+        history
+    }
+
+    /// A history that lives only as long as the session
+    #[cfg(test)]
+    fn in_memory() -> Self {
+        Self {
+            entries: Vec::new(),
+            path: None,
+            position: None,
+            draft: String::new(),
+        }
+    }
+
+    /// Remembers a sent line, repeating the previous one is not remembered twice
+    pub(crate) fn push(&mut self, entry: &str) {
+        self.position = None;
+        self.draft.clear();
+        // NOTE: passwords must never end up in the history file
+        if entry.trim().is_empty()
+            || entry.starts_with("/password")
+            || self.entries.last().is_some_and(|last| last == entry)
+        {
+            return;
+        }
+        self.entries.push(entry.to_string());
+        if self.entries.len() > HISTORY_CAPACITY {
+            self.entries.remove(0);
+        }
+        self.append(entry);
+    }
+
+    /// The entry before the recalled one, `current` is the line being edited
+    pub(crate) fn previous(&mut self, current: &str) -> Option<&str> {
+        let position = match self.position {
+            None => {
+                self.draft = current.to_string();
+                self.entries.len().checked_sub(1)?
+            }
+            Some(position) => position.checked_sub(1)?,
+        };
+        self.position = Some(position);
+        Some(&self.entries[position])
+    }
+
+    /// The entry after the recalled one, past the newest entry that's the line being edited
+    pub(crate) fn next(&mut self) -> Option<&str> {
+        let position = self.position? + 1;
+        if position == self.entries.len() {
+            self.position = None;
+            return Some(&self.draft);
+        }
+        self.position = Some(position);
+        Some(&self.entries[position])
+    }
+
+    /// Index of the newest entry before `before` that contains the query
+    pub(crate) fn search(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // NOTE: the history is a convenience, failing to persist it is not worth bothering the
+    // user with, so the I/O errors below are ignored
+    fn append(&self, entry: &str) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", encode(entry)));
+    }
+
+    fn rewrite(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let text: String = self
+            .entries
+            .iter()
+            .map(|entry| format!("{}\n", encode(entry)))
+            .collect();
+        let _ = fs::write(path, text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_navigation_and_search() {
+        let mut history = InputHistory::in_memory();
+        for entry in ["hello", "/nick bob", "/nick bob", "how are you"] {
+            history.push(entry);
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.previous("draft"), Some("how are you"));
+        assert_eq!(history.previous(""), Some("/nick bob"));
+        assert_eq!(history.previous(""), Some("hello"));
+        assert_eq!(history.previous(""), None);
+        assert_eq!(history.next(), Some("/nick bob"));
+        assert_eq!(history.next(), Some("how are you"));
+        assert_eq!(history.next(), Some("draft"));
+        assert_eq!(history.next(), None);
+
+        assert_eq!(history.search("h", history.len()), Some(2));
+        assert_eq!(history.search("h", 2), Some(0));
+        assert_eq!(history.search("nope", 3), None);
+    }
+
+    #[test]
+    fn test_encoding() {
+        let entry = "two\nlines \\n";
+        assert!(!encode(entry).contains('\n'));
+        assert_eq!(decode(&encode(entry)), entry);
+    }
+}
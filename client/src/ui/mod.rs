@@ -1,3 +1,7 @@
+pub(crate) mod command;
+pub(crate) mod completion;
 mod dispatch;
+pub(crate) mod history;
 pub(crate) mod page;
+pub(crate) mod theme;
 pub(crate) mod ui_manager;
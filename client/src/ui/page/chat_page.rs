@@ -1,29 +1,60 @@
-use std::collections::HashSet;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
 
-use crossterm::event::{KeyCode, KeyModifiers};
+use crossterm::event::{KeyCode, KeyModifiers, MouseEvent, MouseEventKind};
 use ratatui::{
-    layout::{Alignment, Constraint, Direction, Layout, Margin},
-    style::Stylize,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Style, Stylize},
     symbols,
     text::{Line, Span},
-    widgets::{block::Title, Block, Borders, List, ListItem, Paragraph},
+    widgets::{
+        block::{Position, Title},
+        Block, Borders, Clear, List, ListItem, ListState, Padding, Paragraph,
+    },
 };
 use tokio::sync::mpsc::UnboundedSender;
+use unicode_width::UnicodeWidthStr;
+
+use shared::message::Presence;
 
 use crate::{
-    client::ClientInput, state::action::Action, state::chat::ChatLog, state::state::State,
+    client::{text_position, ClientInput},
+    state::action::Action,
+    state::chat::{find_ignore_case, ChatLog, ChatMessage, SearchQuery},
+    state::markdown::Format,
+    state::state::{OnlineUser, State},
+    ui::command::{self, Input},
+    ui::completion::{self, Completion},
+    ui::history::InputHistory,
+    ui::theme::Theme,
 };
 
 use super::widget::Widget;
 
 const USER_ICON: &str = "";
+// Minimum delay between two "typing" notifications sent to the server
+const TYPING_THROTTLE: Duration = Duration::from_secs(2);
+// Lines the input area grows to before it starts scrolling
+const MAX_INPUT_LINES: u16 = 8;
+// Messages scrolled by one notch of the mouse wheel
+const WHEEL_SCROLL: usize = 3;
+// Completion candidates listed at once in the popup
+const COMPLETION_POPUP_LINES: u16 = 8;
 
 struct ChatPageState {
     login_name: Option<String>,
     messages_sent: u64,
     chat_messages: ChatLog,
-    online_users: HashSet<String>,
+    online_users: BTreeMap<String, OnlineUser>,
     time_online: u64,
+    typing_users: Vec<String>,
+    presence: Presence,
+    status_text: String,
+    unread_mentions: u64,
+    rooms: Vec<String>,
+    current_room: Option<String>,
 }
 
 impl From<State> for ChatPageState {
@@ -34,14 +65,71 @@ impl From<State> for ChatPageState {
             chat_messages: value.chat_messages,
             online_users: value.online_users,
             time_online: value.timer.round() as u64,
+            typing_users: {
+                let mut typing_users: Vec<String> = value.typing_users.into_keys().collect();
+                typing_users.sort();
+                typing_users
+            },
+            presence: value.presence,
+            status_text: value.status_text,
+            unread_mentions: value.unread_mentions,
+            rooms: value.rooms.into_iter().collect(),
+            current_room: value.current_room,
         }
     }
 }
 
+/// A Ctrl-R reverse incremental search through the input history
+#[derive(Default)]
+struct HistorySearch {
+    query: String,
+    // Index of the matching history entry
+    found: Option<usize>,
+}
+
+/// A search through the chat log, the query is typed first and the matches are then
+/// browsed with n/N
+#[derive(Default)]
+struct ChatSearch {
+    source: String,
+    editing: bool,
+    query: SearchQuery,
+    // Sequence numbers of the matching messages, the oldest first
+    matches: Vec<u64>,
+    // Index of the match scrolled to
+    current: Option<usize>,
+}
+
+impl ChatSearch {
+    fn start() -> Self {
+        Self {
+            editing: true,
+            ..Self::default()
+        }
+    }
+
+    fn current_sequence(&self) -> Option<u64> {
+        self.current.map(|current| self.matches[current])
+    }
+}
+
 pub(crate) struct ChatPage {
     action_tx: UnboundedSender<Action>,
     page_state: ChatPageState,
     input: ClientInput,
+    history: InputHistory,
+    history_search: Option<HistorySearch>,
+    chat_search: Option<ChatSearch>,
+    completion: Option<Completion>,
+    // Messages between the newest one and the bottom of the view, 0 follows the chat
+    scroll: usize,
+    // Messages that arrived while scrolled up
+    unseen_below: u64,
+    // NOTE: where the messages were last drawn, scrolling by pages depends on it
+    chat_area: Cell<Rect>,
+    last_typing_sent: Option<Instant>,
+    selected_chatter: usize,
+    show_chatter_details: bool,
 }
 
 impl ChatPage {
@@ -50,115 +138,829 @@ impl ChatPage {
             action_tx,
             page_state: ChatPageState::from(state),
             input: ClientInput::new(),
+            history: InputHistory::load(),
+            history_search: None,
+            chat_search: None,
+            completion: None,
+            scroll: 0,
+            unseen_below: 0,
+            chat_area: Cell::new(Rect::default()),
+            last_typing_sent: None,
+            selected_chatter: 0,
+            show_chatter_details: false,
+        }
+    }
+
+    fn selected_chatter_index(&self) -> Option<usize> {
+        let last = self.page_state.online_users.len().checked_sub(1)?;
+        Some(self.selected_chatter.min(last))
+    }
+
+    fn selected_chatter(&self) -> Option<(&String, &OnlineUser)> {
+        self.page_state
+            .online_users
+            .iter()
+            .nth(self.selected_chatter_index()?)
+    }
+
+    /// Navigation while the chatter details popup is open, returns whether the key was consumed
+    fn handle_chatter_details_key(&mut self, key: crossterm::event::KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Up => self.selected_chatter = self.selected_chatter.saturating_sub(1),
+            KeyCode::Down => {
+                self.selected_chatter = (self.selected_chatter + 1)
+                    .min(self.page_state.online_users.len().saturating_sub(1))
+            }
+            KeyCode::Esc | KeyCode::F(3) => self.show_chatter_details = false,
+            _ => return false,
+        }
+        true
+    }
+
+    fn scroll_up(&mut self, messages: usize) {
+        let max_scroll = self
+            .page_state
+            .chat_messages
+            .max_scroll(&self.chat_area.get());
+        self.scroll = self.scroll.saturating_add(messages).min(max_scroll);
+    }
+
+    fn scroll_down(&mut self, messages: usize) {
+        self.scroll = self.scroll.saturating_sub(messages);
+        if self.scroll == 0 {
+            self.unseen_below = 0;
+        }
+    }
+
+    /// Messages in a screenful, one is kept in view when paging
+    fn page_size(&self) -> usize {
+        self.page_state
+            .chat_messages
+            .get_fitting_messages(&self.chat_area.get(), self.scroll)
+            .len()
+            .saturating_sub(1)
+            .max(1)
+    }
+
+    /// Completes the word before the cursor, or cycles through the completions if several
+    /// were found. A single completion is taken right away
+    fn complete(&mut self, forward: bool) {
+        if let Some(completion) = &mut self.completion {
+            if forward {
+                completion.next();
+            } else {
+                completion.previous();
+            }
+            self.input
+                .replace_before_cursor(completion.start, completion.selected());
+            return;
+        }
+        let (start, word) = self.input.word_before_cursor();
+        let login_name = self.page_state.login_name.as_deref();
+        let nicks = self
+            .page_state
+            .online_users
+            .keys()
+            .map(String::as_str)
+            .filter(|name| Some(*name) != login_name);
+        // NOTE: only the joined rooms are known, the server doesn't list the others
+        let rooms = self
+            .page_state
+            .rooms
+            .iter()
+            .map(|room| room.trim_start_matches('#'));
+        let candidates = completion::candidates(&self.input.as_str()[..start], word, nicks, rooms);
+        match candidates.as_slice() {
+            [] => {}
+            [only] => {
+                let completed = format!("{only} ");
+                self.input.replace_before_cursor(start, &completed);
+            }
+            _ => {
+                let completion = Completion {
+                    start,
+                    selected: if forward { 0 } else { candidates.len() - 1 },
+                    candidates,
+                };
+                self.input
+                    .replace_before_cursor(start, completion.selected());
+                self.completion = Some(completion);
+            }
+        }
+    }
+
+    fn render_completion(
+        &self,
+        frame: &mut ratatui::prelude::Frame,
+        input_area: Rect,
+        theme: &Theme,
+    ) {
+        let Some(completion) = &self.completion else {
+            return;
+        };
+        let width = completion
+            .candidates
+            .iter()
+            .map(|candidate| candidate.width() as u16)
+            .max()
+            .unwrap_or_default()
+            + 4;
+        let height = (completion.candidates.len() as u16).min(COMPLETION_POPUP_LINES) + 2;
+        let (column, line) = text_position(self.input.as_str(), completion.start);
+        let frame_area = frame.size();
+        let area = Rect::new(
+            (input_area.x + 1 + column).min(frame_area.width.saturating_sub(width)),
+            (input_area.y + 1 + line).saturating_sub(height),
+            width.min(frame_area.width),
+            height.min(frame_area.height),
+        );
+        let items = completion
+            .candidates
+            .iter()
+            .map(|candidate| ListItem::new(candidate.as_str()));
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_set(symbols::border::ROUNDED)
+            .border_style(Style::default().fg(theme.border))
+            .style(theme.base())
+            .padding(Padding::horizontal(1));
+        let mut state = ListState::default().with_selected(Some(completion.selected));
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(block)
+                .highlight_style(Style::default().reversed()),
+            area,
+            &mut state,
+        );
+    }
+
+    /// Scrolls the current search match into view
+    fn scroll_to_match(&mut self) {
+        let Some(sequence) = self
+            .chat_search
+            .as_ref()
+            .and_then(ChatSearch::current_sequence)
+        else {
+            return;
+        };
+        let log = &self.page_state.chat_messages;
+        if let Some(scroll) = log.scroll_to(sequence) {
+            self.scroll = scroll.min(log.max_scroll(&self.chat_area.get()));
+            if self.scroll == 0 {
+                self.unseen_below = 0;
+            }
+        }
+    }
+
+    /// Keys while searching the chat, returns whether the key was consumed. While the query
+    /// is typed every key is, while going through the matches with n and N any other key
+    /// that's not for scrolling ends the search and goes on to the input
+    fn handle_chat_search_key(&mut self, key: crossterm::event::KeyEvent) -> bool {
+        let Some(search) = &mut self.chat_search else {
+            return false;
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        if search.editing {
+            match key.code {
+                KeyCode::Enter => {
+                    search.query = SearchQuery::parse(&search.source);
+                    if search.query.is_empty() {
+                        self.chat_search = None;
+                        return true;
+                    }
+                    search.editing = false;
+                    search.matches = self.page_state.chat_messages.search(&search.query);
+                    search.current = search.matches.len().checked_sub(1);
+                    self.scroll_to_match();
+                }
+                KeyCode::Esc => self.chat_search = None,
+                KeyCode::Backspace => {
+                    search.source.pop();
+                }
+                KeyCode::Char(c) if !ctrl && !alt => search.source.push(c),
+                _ => {}
+            }
+            return true;
+        }
+        match key.code {
+            KeyCode::Char('n') if !ctrl && !alt => {
+                search.current = search.current.map(|current| current.saturating_sub(1));
+                self.scroll_to_match();
+            }
+            KeyCode::Char('N') if !ctrl && !alt => {
+                search.current = search
+                    .current
+                    .map(|current| (current + 1).min(search.matches.len() - 1));
+                self.scroll_to_match();
+            }
+            KeyCode::Char('f') if ctrl => search.editing = true,
+            KeyCode::Esc => self.chat_search = None,
+            KeyCode::PageUp | KeyCode::PageDown => return false,
+            KeyCode::Home | KeyCode::End if ctrl => return false,
+            _ => {
+                self.chat_search = None;
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Keys while searching the history, returns whether the key was consumed. Any key
+    /// that's not part of the search accepts the match and then does what it usually does
+    fn handle_history_search_key(&mut self, key: crossterm::event::KeyEvent) -> bool {
+        let Some(search) = &mut self.history_search else {
+            return false;
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('r') if ctrl => {
+                let before = search.found.unwrap_or(self.history.len());
+                if let Some(found) = self.history.search(&search.query, before) {
+                    search.found = Some(found);
+                }
+            }
+            KeyCode::Char('g') if ctrl => self.history_search = None,
+            KeyCode::Esc => self.history_search = None,
+            KeyCode::Char(c) if !ctrl && !key.modifiers.contains(KeyModifiers::ALT) => {
+                search.query.push(c);
+                // NOTE: the current match is kept as long as it still matches
+                let before = search.found.map_or(self.history.len(), |found| found + 1);
+                search.found = self.history.search(&search.query, before);
+            }
+            KeyCode::Backspace => {
+                search.query.pop();
+                search.found = self.history.search(&search.query, self.history.len());
+            }
+            code => {
+                if let Some(entry) = search.found.and_then(|found| self.history.get(found)) {
+                    self.input.set(entry);
+                }
+                self.history_search = None;
+                return code == KeyCode::Enter;
+            }
+        }
+        true
+    }
+
+    fn render_chatter_details(&self, frame: &mut ratatui::prelude::Frame, theme: &Theme) {
+        let Some((name, user)) = self.selected_chatter() else {
+            return;
+        };
+        let area = centered_rect(frame.size(), 44, 8);
+        let online_for = (chrono::Local::now() - user.joined_at).num_seconds().max(0);
+        let mut status = vec![format!("{}", user.presence).fg(theme.presence_color(user.presence))];
+        if !user.status_text.is_empty() {
+            status.push(format!(" · {}", user.status_text).into());
+        }
+        let lines = vec![
+            Line::from(status),
+            Line::from(format!(
+                "Joined at {} ({}m ago)",
+                user.joined_at.format("%H:%M:%S"),
+                online_for / 60
+            )),
+            Line::from(format!("Messages sent: {}", user.messages_sent)),
+        ];
+        let block = Block::default()
+            .title(Title::from(name.clone().bold()).alignment(Alignment::Left))
+            .title(
+                Title::from("↑↓ select · Esc close".fg(theme.hint))
+                    .alignment(Alignment::Right)
+                    .position(Position::Bottom),
+            )
+            .borders(Borders::ALL)
+            .border_set(symbols::border::ROUNDED)
+            .border_style(Style::default().fg(theme.border))
+            .style(theme.base())
+            .padding(Padding::horizontal(1));
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    fn notify_typing(&mut self) {
+        if self.input.as_str().is_empty()
+            || self
+                .last_typing_sent
+                .is_some_and(|sent| sent.elapsed() < TYPING_THROTTLE)
+        {
+            return;
+        }
+        self.last_typing_sent = Some(Instant::now());
+        self.action_tx
+            .send(Action::Typing)
+            .expect("Receiver unexpectedly dropped");
+    }
+
+    /// The text shown in the input area and the cursor's column and line in it. While
+    /// searching the history the match is shown in place of the input
+    fn input_view(&self) -> (&str, (u16, u16)) {
+        if let Some(search) = self.chat_search.as_ref().filter(|search| search.editing) {
+            return (
+                &search.source,
+                text_position(&search.source, search.source.len()),
+            );
+        }
+        let Some(search) = &self.history_search else {
+            return (
+                self.input.as_str(),
+                (self.input.cursor_width(), self.input.cursor_line()),
+            );
+        };
+        let entry = search
+            .found
+            .and_then(|found| self.history.get(found))
+            .unwrap_or_default();
+        let query_end = entry
+            .find(&search.query)
+            .map_or(0, |start| start + search.query.len());
+        (entry, text_position(entry, query_end))
+    }
+
+    /// Height of the input area without its borders
+    fn input_lines(&self) -> u16 {
+        let (text, _) = self.input_view();
+        (text.split('\n').count() as u16).min(MAX_INPUT_LINES)
+    }
+
+    /// Color of the author of a message, their own for the user and a hashed one for others
+    fn author_color(&self, message: &ChatMessage, theme: &Theme) -> Color {
+        match (message.is_system(), message.sender()) {
+            (true, "Error") => theme.error,
+            (true, _) => theme.system,
+            (false, author) if Some(author) == self.page_state.login_name.as_deref() => theme.own,
+            (false, author) => theme.nick_color(author),
+        }
+    }
+
+    /// One of the wrapped lines of a message, the first one starts with the timestamp and
+    /// the author
+    fn message_line(
+        &self,
+        message: &ChatMessage,
+        line: &[(String, Format)],
+        search: Option<&str>,
+        is_current: bool,
+        theme: &Theme,
+    ) -> Line<'static> {
+        let author_color = self.author_color(message, theme);
+        let text_style = if message.is_system() {
+            Style::default().fg(author_color)
+        } else {
+            Style::default()
+        };
+        let match_style = theme.match_style(is_current);
+        let byline = message.byline();
+        let mut spans = Vec::new();
+        for (i, (text, format)) in line.iter().enumerate() {
+            let text = match text
+                .strip_prefix(message.timestamp())
+                .and_then(|rest| rest.strip_prefix(' '))
+                .and_then(|rest| rest.strip_prefix(byline.as_str()))
+                .filter(|_| i == 0)
+            {
+                Some(body) => {
+                    spans.push(Span::styled(
+                        format!("{} ", message.timestamp()),
+                        Style::default().fg(theme.timestamp),
+                    ));
+                    spans.push(Span::styled(
+                        byline.clone(),
+                        Style::default().fg(author_color),
+                    ));
+                    body
+                }
+                None => text,
+            };
+            spans.extend(highlight(
+                text,
+                search,
+                format_style(text_style, *format, theme),
+                match_style,
+            ));
+        }
+        let line = Line::from(spans);
+        match (is_current, message.mentions_me()) {
+            (true, _) => line.bold(),
+            (false, true) => line.style(theme.mention_style()),
+            (false, false) => line,
+        }
+    }
+
+    fn input_title(&self) -> String {
+        if let Some(search) = &self.chat_search {
+            if search.editing {
+                return "Search — from:name after:hh:mm before:hh:mm text · Enter search · Esc cancel"
+                    .to_string();
+            }
+            return match search.current {
+                Some(current) => format!(
+                    "Search — {} of {} · n older · N newer · Esc close",
+                    current + 1,
+                    search.matches.len()
+                ),
+                None => "Search — no matches · Esc close".to_string(),
+            };
+        }
+        if let Some(search) = &self.history_search {
+            return match search.found {
+                Some(_) => format!("Input — reverse-i-search `{}'", search.query),
+                None => format!("Input — failed reverse-i-search `{}'", search.query),
+            };
         }
+        let input = match &self.page_state.current_room {
+            Some(room) => format!("Input {room}"),
+            None => "Input".to_string(),
+        };
+        match self.page_state.typing_users.as_slice() {
+            [] => input,
+            [one] => format!("{input} — {one} is typing…"),
+            [first, second] => format!("{input} — {first} and {second} are typing…"),
+            _ => format!("{input} — several people are typing…"),
+        }
+    }
+}
+
+fn centered_rect(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+fn next_presence(presence: Presence) -> Presence {
+    match presence {
+        Presence::Online => Presence::Away,
+        Presence::Away => Presence::Busy,
+        Presence::Busy => Presence::DoNotDisturb,
+        Presence::DoNotDisturb => Presence::Online,
+    }
+}
+
+/// The style of markdown formatted text
+fn format_style(mut style: Style, format: Format, theme: &Theme) -> Style {
+    if format.bold {
+        style = style.bold();
+    }
+    if format.italic {
+        style = style.italic();
+    }
+    if format.strike {
+        style = style.crossed_out();
+    }
+    if format.code || format.code_block {
+        style = style.patch(theme.code_style());
+    }
+    style
+}
+
+/// The text with the occurrences of the search text in the match style
+// NOTE: a match that's wrapped over two lines is not highlighted
+fn highlight(
+    text: &str,
+    search: Option<&str>,
+    style: Style,
+    match_style: Style,
+) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for range in search
+        .map(|search| find_ignore_case(text, search))
+        .unwrap_or_default()
+    {
+        spans.push(Span::styled(text[last..range.start].to_string(), style));
+        spans.push(Span::styled(text[range.clone()].to_string(), match_style));
+        last = range.end;
+    }
+    spans.push(Span::styled(text[last..].to_string(), style));
+    spans
+}
+
+fn ring_bell() {
+    let mut stdout = std::io::stdout();
+    // NOTE: the bell is best effort, a terminal that can't ring it is not an error
+    let _ = stdout.write_all(b"\x07").and_then(|_| stdout.flush());
+}
+
+fn parse_reaction(source: &str) -> Option<String> {
+    let emoji = source.strip_prefix('+')?;
+    if emoji.is_empty() || emoji.chars().any(char::is_whitespace) {
+        return None;
     }
+    Some(emoji.to_string())
 }
 
 impl Widget for ChatPage {
     fn handle_key_event(&mut self, key: crossterm::event::KeyEvent) {
+        if self.show_chatter_details && self.handle_chatter_details_key(key) {
+            return;
+        }
+        if self.handle_history_search_key(key) || self.handle_chat_search_key(key) {
+            return;
+        }
         match key.code {
-            KeyCode::Char(c) if key.modifiers.is_empty() => self.input.insert(c),
-            KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::SHIFT) => {
-                self.input.insert_uppercase(c.to_uppercase())
+            KeyCode::Tab => return self.complete(true),
+            KeyCode::BackTab => return self.complete(false),
+            KeyCode::Esc if self.completion.is_some() => {
+                self.completion = None;
+                return;
             }
-            KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                self.input.backspace_forward()
+            // NOTE: any other key settles on the completion shown
+            _ => self.completion = None,
+        }
+        match key.code {
+            KeyCode::F(2) => {
+                self.action_tx
+                    .send(Action::SetStatus {
+                        presence: next_presence(self.page_state.presence),
+                        status_text: self.page_state.status_text.clone(),
+                    })
+                    .expect("Receiver unexpectedly dropped");
+            }
+            KeyCode::F(3) => self.show_chatter_details = true,
+            // NOTE: the current input becomes the status text, an empty input clears it
+            KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let status_text = self.input.as_str().to_string();
+                self.input.clear();
+                self.action_tx
+                    .send(Action::SetStatus {
+                        presence: self.page_state.presence,
+                        status_text,
+                    })
+                    .expect("Receiver unexpectedly dropped");
             }
-            KeyCode::Backspace => self.input.backspace(),
-            KeyCode::Left => self.input.left(),
-            KeyCode::Right => self.input.right(),
             KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                 self.action_tx
                     .send(Action::Quit)
                     .expect("Receiver unexpectedly dropped");
             }
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.history_search = Some(HistorySearch::default());
+            }
+            KeyCode::Char('f') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.chat_search = Some(ChatSearch::start());
+            }
+            // NOTE: "/" starts a command in the input, it only searches while browsing the
+            // chat history with an empty input, which /help tells
+            KeyCode::Char('/') if self.scroll > 0 && self.input.as_str().is_empty() => {
+                self.chat_search = Some(ChatSearch::start());
+            }
+            KeyCode::PageUp => self.scroll_up(self.page_size()),
+            KeyCode::PageDown => self.scroll_down(self.page_size()),
+            // NOTE: plain Home/End belong to the input
+            KeyCode::Home if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.scroll_up(usize::MAX)
+            }
+            KeyCode::End if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.scroll_down(usize::MAX)
+            }
+            // NOTE: Up/Down move between the lines of the input, the history is recalled
+            // from its first and last line
+            KeyCode::Up => {
+                if self.input.up() {
+                    return;
+                }
+                if let Some(entry) = self.history.previous(self.input.as_str()) {
+                    self.input.set(entry);
+                }
+            }
+            KeyCode::Down => {
+                if self.input.down() {
+                    return;
+                }
+                if let Some(entry) = self.history.next() {
+                    self.input.set(entry);
+                }
+            }
+            // NOTE: terminals without the keyboard enhancement protocol can't tell Shift-Enter
+            // from Enter, Alt-Enter works everywhere
+            KeyCode::Enter
+                if key
+                    .modifiers
+                    .intersects(KeyModifiers::SHIFT | KeyModifiers::ALT) =>
+            {
+                self.input.insert('\n');
+                self.notify_typing();
+            }
             KeyCode::Enter => {
-                let source: String = self.input.get_ref().iter().collect();
+                let source = self.input.as_str().to_string();
                 self.input.clear();
+                self.history.push(&source);
+                self.last_typing_sent = None;
+                let action = if let Some(emoji) = parse_reaction(&source) {
+                    // NOTE: "+emoji" reacts to the latest message, like in slack
+                    match self.page_state.chat_messages.last_message_id() {
+                        Some(message_id) => Action::React { message_id, emoji },
+                        None => return,
+                    }
+                } else {
+                    match command::parse(&source) {
+                        Ok(Input::Text(message)) if message.trim().is_empty() => return,
+                        Ok(Input::Text(message)) => Action::SendMessage { message },
+                        Ok(Input::Command(action)) => action,
+                        Err(usage) => Action::ShowSystemMessage { message: usage },
+                    }
+                };
                 self.action_tx
-                    .send(Action::SendMessage { message: source })
+                    .send(action)
                     .expect("Receiver unexpectedly dropped");
             }
+            _ => {
+                if self.input.handle_key(key) {
+                    self.notify_typing();
+                }
+            }
+        }
+    }
+    fn handle_mouse_event(&mut self, mouse: MouseEvent) {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => self.scroll_up(WHEEL_SCROLL),
+            MouseEventKind::ScrollDown => self.scroll_down(WHEEL_SCROLL),
             _ => {}
         }
     }
+
     fn update(&mut self, state: State) {
+        let unread_mentions = self.page_state.unread_mentions;
+        let messages_received = self.page_state.chat_messages.messages_received();
         self.page_state = ChatPageState::from(state);
+        if self.page_state.unread_mentions > unread_mentions {
+            ring_bell();
+        }
+        // NOTE: while scrolled up the view stays on the same messages as new ones arrive
+        let arrived = self
+            .page_state
+            .chat_messages
+            .messages_received()
+            .saturating_sub(messages_received);
+        if self.scroll > 0 && arrived > 0 {
+            self.unseen_below += arrived;
+            self.scroll_up(arrived as usize);
+        }
     }
 
-    fn render(&self, frame: &mut ratatui::prelude::Frame) {
+    fn render(&self, frame: &mut ratatui::prelude::Frame, theme: &Theme) {
         let [left, right] = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([Constraint::Percentage(80), Constraint::Percentage(20)])
             .areas(frame.size());
         let [chat_area, input_area] = Layout::default()
             .direction(Direction::Vertical)
-            .constraints([Constraint::Percentage(100), Constraint::Min(3)])
+            .constraints([
+                Constraint::Min(0),
+                Constraint::Length(self.input_lines() + 2),
+            ])
             .areas(left);
         let [chatters_area, user_info_area] = Layout::default()
             .direction(Direction::Vertical)
-            .constraints([Constraint::Percentage(100), Constraint::Min(5)])
+            .constraints([Constraint::Percentage(100), Constraint::Min(7)])
             .areas(right);
 
-        let chat_block = Block::default()
+        let mut chat_block = Block::default()
             .title(Title::from("Messages".bold()).alignment(Alignment::Left))
             .borders(Borders::ALL)
-            .border_set(symbols::border::ROUNDED);
+            .border_set(symbols::border::ROUNDED)
+            .border_style(Style::default().fg(theme.border));
+        if self.scroll > 0 {
+            let indicator = match self.unseen_below {
+                0 => "Ctrl-End to follow".fg(theme.hint),
+                1 => "↓ 1 new message below".fg(theme.highlight).bold(),
+                n => format!("↓ {n} new messages below")
+                    .fg(theme.highlight)
+                    .bold(),
+            };
+            chat_block = chat_block.title(
+                Title::from(indicator)
+                    .alignment(Alignment::Right)
+                    .position(Position::Bottom),
+            );
+        }
         let input_block = Block::default()
-            .title(Title::from("Input".bold()).alignment(Alignment::Left))
+            .title(Title::from(self.input_title().bold()).alignment(Alignment::Left))
             .borders(Borders::ALL)
             .border_set(symbols::border::ROUNDED)
-            .green();
+            .border_style(Style::default().fg(theme.input_border));
 
         let chatters_block = Block::default()
             .title(Title::from("Chatters".bold()).alignment(Alignment::Left))
             .borders(Borders::ALL)
-            .border_set(symbols::border::ROUNDED);
+            .border_set(symbols::border::ROUNDED)
+            .border_style(Style::default().fg(theme.border));
         let user_info_block = Block::default()
             .title(Title::from("User info".bold()).alignment(Alignment::Left))
+            .title(
+                Title::from("F2 presence · ^S status · F3 who · F4 theme".fg(theme.hint))
+                    .alignment(Alignment::Right)
+                    .position(Position::Bottom),
+            )
             .borders(Borders::ALL)
-            .border_set(symbols::border::ROUNDED);
+            .border_set(symbols::border::ROUNDED)
+            .border_style(Style::default().fg(theme.border));
 
-        let chat_lines = self
-            .page_state
-            .chat_messages
-            .get_fitting_messages(&chat_area.inner(&Margin::new(0, 1)))
+        // NOTE: messages are wrapped to the same width they are fitted with, so the newest
+        // message always ends up at the bottom
+        let chat_inner = chat_block.inner(chat_area);
+        self.chat_area.set(chat_inner);
+        let log = &self.page_state.chat_messages;
+        let fitting_messages = log.get_fitting_messages(&chat_inner, self.scroll);
+        let first_sequence = log.first_sequence()
+            + log
+                .len()
+                .saturating_sub(self.scroll + fitting_messages.len()) as u64;
+        let search = self.chat_search.as_ref().filter(|search| !search.editing);
+        let chat_lines = fitting_messages
             .into_iter()
-            .map(|l| {
-                let msg = Line::from(Span::raw(format!("{}", l)));
-                ListItem::new(msg)
+            .zip(first_sequence..)
+            .map(|(l, sequence)| {
+                let mut lines = Vec::new();
+                if log.is_first_new(&l) {
+                    lines.push(Line::from("── new messages ──".fg(theme.divider)).centered());
+                }
+                let is_current = search.is_some_and(|s| s.current_sequence() == Some(sequence));
+                let highlight = search
+                    .filter(|s| s.query.matches(&l))
+                    .map(|s| s.query.text.as_str());
+                lines.extend(
+                    l.wrap(chat_inner.width)
+                        .iter()
+                        .map(|line| self.message_line(&l, line, highlight, is_current, theme)),
+                );
+                if let Some(reactions) = l.reactions_line() {
+                    lines.push(Line::from(format!("  {reactions}").fg(theme.hint)));
+                }
+                // NOTE: only a message taller than the whole pane gets clipped, to its last
+                // lines like a terminal would show it
+                lines.drain(..lines.len().saturating_sub(chat_inner.height as usize));
+                ListItem::new(lines)
             });
 
-        let chatters_lines = self.page_state.online_users.iter().map(|l| {
-            let chatter = if *l == *self.page_state.login_name.as_ref().unwrap() {
-                "You".green()
+        let chatters_lines = self.page_state.online_users.iter().map(|(name, user)| {
+            let chatter = if *name == *self.page_state.login_name.as_ref().unwrap() {
+                "You".fg(theme.own)
             } else {
-                l.clone().white()
+                name.clone().fg(theme.nick_color(name))
             };
-            let item = Line::from(vec![Span::from(format!("{} ", USER_ICON)), chatter]);
-            ListItem::new(item)
+            let mut spans = vec![
+                Span::from(format!("{} ", USER_ICON)).fg(theme.presence_color(user.presence)),
+                chatter,
+                format!(" ({})", user.messages_sent).fg(theme.hint),
+            ];
+            if !user.status_text.is_empty() {
+                spans.push(format!(" {}", user.status_text).fg(theme.hint));
+            }
+            ListItem::new(Line::from(spans))
         });
 
         let user_info_lines = {
             let user_name_line =
                 Line::from(self.page_state.login_name.as_ref().unwrap().to_string());
+            let mut status = vec![format!("{}", self.page_state.presence)
+                .fg(theme.presence_color(self.page_state.presence))];
+            if !self.page_state.status_text.is_empty() {
+                status.push(format!(" · {}", self.page_state.status_text).into());
+            }
+            let status_line = Line::from(status);
             let messages_sent = Line::from(format!("Sent: {}", self.page_state.messages_sent));
             let time_online = Line::from(format!("Online for {}s", self.page_state.time_online));
+            let mentions = match self.page_state.unread_mentions {
+                0 => Line::from("Mentions: 0"),
+                n => Line::from(format!("Mentions: {n}").fg(theme.highlight).bold()),
+            };
             vec![
                 ListItem::new(user_name_line),
+                ListItem::new(status_line),
                 ListItem::new(messages_sent),
                 ListItem::new(time_online),
+                ListItem::new(mentions),
             ]
         };
 
         frame.render_widget(List::new(chat_lines).block(chat_block), chat_area);
+        let (input_text, (cursor_column, cursor_line)) = self.input_view();
+        let scroll = cursor_line.saturating_sub(MAX_INPUT_LINES - 1);
         frame.render_widget(
-            Paragraph::new(self.input.get_ref().iter().collect::<String>()).block(input_block),
+            Paragraph::new(input_text)
+                .scroll((scroll, 0))
+                .block(input_block),
             input_area,
         );
-        frame.render_widget(
-            List::new(chatters_lines).block(chatters_block),
+        let mut chatters_state = ListState::default().with_selected(
+            self.show_chatter_details
+                .then(|| self.selected_chatter_index())
+                .flatten(),
+        );
+        frame.render_stateful_widget(
+            List::new(chatters_lines)
+                .block(chatters_block)
+                .highlight_style(Style::default().reversed()),
             chatters_area,
+            &mut chatters_state,
         );
         frame.render_widget(
             List::new(user_info_lines).block(user_info_block),
@@ -166,8 +968,13 @@ impl Widget for ChatPage {
         );
 
         frame.set_cursor(
-            input_area.x + 1 + self.input.get_ref().len() as u16,
-            input_area.y + 1,
-        )
+            input_area.x + 1 + cursor_column,
+            input_area.y + 1 + cursor_line - scroll,
+        );
+
+        self.render_completion(frame, input_area, theme);
+        if self.show_chatter_details {
+            self.render_chatter_details(frame, theme);
+        }
     }
 }
@@ -1,8 +1,10 @@
 use crate::ui::page::widget::Widget;
+use crate::ui::theme::Theme;
 use crate::{client::ClientInput, state::action::Action};
 use crossterm::event::{KeyCode, KeyModifiers};
 use ratatui::layout::{Alignment, Constraint, Direction, Layout};
 use ratatui::prelude::Stylize;
+use ratatui::style::Style;
 use ratatui::symbols;
 use ratatui::text::Line;
 use ratatui::widgets::block::{Position, Title};
@@ -12,6 +14,7 @@ use tokio::sync::mpsc::UnboundedSender;
 pub(crate) struct LoginPage {
     action_tx: UnboundedSender<Action>,
     input: ClientInput,
+    disconnect_reason: Option<String>,
 }
 
 impl LoginPage {
@@ -19,6 +22,7 @@ impl LoginPage {
         Self {
             action_tx,
             input: ClientInput::new(),
+            disconnect_reason: None,
         }
     }
 }
@@ -26,40 +30,34 @@ impl LoginPage {
 impl Widget for LoginPage {
     fn handle_key_event(&mut self, key: crossterm::event::KeyEvent) {
         match key.code {
-            KeyCode::Char(c) if key.modifiers.is_empty() => self.input.insert(c),
-            KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::SHIFT) => {
-                self.input.insert_uppercase(c.to_uppercase())
-            }
-            KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                self.input.backspace_forward()
-            }
-            KeyCode::Backspace => self.input.backspace(),
-            KeyCode::Left => self.input.left(),
-            KeyCode::Right => self.input.right(),
             KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                 self.action_tx
                     .send(Action::Quit)
                     .expect("Receiver unexpectedly dropped");
             }
             KeyCode::Enter => {
-                let source: String = self.input.get_ref().iter().collect();
+                let source = self.input.as_str().to_string();
                 self.input.clear();
                 self.action_tx
                     .send(Action::ConnectAndLogin { name: source })
                     .expect("Receiver unexpectedly dropped");
             }
-            _ => {}
+            _ => {
+                self.input.handle_key(key);
+            }
         }
     }
-    fn update(&mut self, _state: crate::state::state::State) {}
+    fn update(&mut self, state: crate::state::state::State) {
+        self.disconnect_reason = state.disconnect_reason;
+    }
 
-    fn render(&self, frame: &mut ratatui::prelude::Frame) {
+    fn render(&self, frame: &mut ratatui::prelude::Frame, theme: &Theme) {
         let title = Title::from("Who are you?".bold());
         let instructions = Title::from(Line::from(vec![
             "Enter ".into(),
-            "your".green().bold(),
+            "your".fg(theme.own).bold(),
             " name".into(),
-            " or".red().bold(),
+            " or".fg(theme.error).bold(),
             " press ^c to quit".into(),
         ]));
         let block = Block::default()
@@ -70,7 +68,8 @@ impl Widget for LoginPage {
                     .position(Position::Bottom),
             )
             .borders(Borders::ALL)
-            .border_set(symbols::border::THICK);
+            .border_set(symbols::border::THICK)
+            .border_style(Style::default().fg(theme.border));
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
@@ -89,20 +88,29 @@ impl Widget for LoginPage {
             .split(chunks[1]);
 
         let input_area = center_chunks[1];
+        if let Some(reason) = &self.disconnect_reason {
+            let [reason_area, _] = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(1), Constraint::Min(0)])
+                .areas(chunks[2]);
+            frame.render_widget(
+                Paragraph::new(format!("Disconnected: {reason}").fg(theme.error)).centered(),
+                reason_area,
+            );
+        }
 
         frame.render_widget(
-            Paragraph::new(self.input.get_ref().iter().collect::<String>())
-                .centered()
-                .block(block),
+            Paragraph::new(self.input.as_str()).centered().block(block),
             input_area,
         );
-        let mut buf_len = self.input.inner.len() as u16;
-        if buf_len % 2 != 0 {
-            buf_len += 1;
-        }
-        frame.set_cursor(
-            input_area.x + input_area.width / 2 + buf_len / 2,
-            input_area.y + 1,
-        );
+        // NOTE: the text is centered inside the borders, the cursor follows it
+        let text_start = input_area.x
+            + 1
+            + input_area
+                .width
+                .saturating_sub(2)
+                .saturating_sub(self.input.width())
+                / 2;
+        frame.set_cursor(text_start + self.input.cursor_width(), input_area.y + 1);
     }
 }
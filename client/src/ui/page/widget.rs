@@ -1,11 +1,14 @@
-use crossterm::event::KeyEvent;
+use crossterm::event::{KeyEvent, MouseEvent};
 use ratatui::prelude::Frame;
 
 use crate::state::state::State;
+use crate::ui::theme::Theme;
 
 // TODO: revisit the need for <P> down where and in render
 pub(crate) trait Widget {
-    fn render(&self, frame: &mut Frame);
+    fn render(&self, frame: &mut Frame, theme: &Theme);
     fn update(&mut self, state: State);
     fn handle_key_event(&mut self, key: KeyEvent);
+
+    fn handle_mouse_event(&mut self, _mouse: MouseEvent) {}
 }
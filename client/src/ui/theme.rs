@@ -0,0 +1,386 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use ratatui::style::{Color, Style};
+use shared::message::Presence;
+
+// Every color a theme file may set, "nicks" takes a comma separated list
+const KEYS: &[&str] = &[
+    "text",
+    "background",
+    "border",
+    "input_border",
+    "timestamp",
+    "system",
+    "error",
+    "own",
+    "hint",
+    "divider",
+    "highlight",
+    "mention",
+    "mention_background",
+    "match_background",
+    "current_match_background",
+    "code",
+    "code_background",
+    "online",
+    "away",
+    "busy",
+    "do_not_disturb",
+    "nicks",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Preset {
+    #[default]
+    Dark,
+    Light,
+    HighContrast,
+}
+
+impl Preset {
+    fn parse(preset: &str) -> Result<Self> {
+        match preset {
+            "dark" => Ok(Self::Dark),
+            "light" => Ok(Self::Light),
+            "high-contrast" => Ok(Self::HighContrast),
+            unknown => bail!("Unknown theme preset: {}", unknown),
+        }
+    }
+
+    /// The preset F4 switches to
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Self::Dark => Self::Light,
+            Self::Light => Self::HighContrast,
+            Self::HighContrast => Self::Dark,
+        }
+    }
+}
+
+/// Colors the pages are drawn with
+#[derive(Clone, Debug)]
+pub(crate) struct Theme {
+    pub(crate) preset: Preset,
+    pub(crate) text: Color,
+    pub(crate) background: Color,
+    pub(crate) border: Color,
+    pub(crate) input_border: Color,
+    pub(crate) timestamp: Color,
+    pub(crate) system: Color,
+    pub(crate) error: Color,
+    // The user's own name
+    pub(crate) own: Color,
+    // Key hints and other secondary text
+    pub(crate) hint: Color,
+    pub(crate) divider: Color,
+    // Counters and indicators that want attention
+    pub(crate) highlight: Color,
+    pub(crate) mention: Color,
+    pub(crate) mention_background: Color,
+    pub(crate) match_background: Color,
+    pub(crate) current_match_background: Color,
+    // Inline code and code blocks in messages
+    pub(crate) code: Color,
+    pub(crate) code_background: Color,
+    // The presence dots and names in the user list and the chatter details
+    pub(crate) online: Color,
+    pub(crate) away: Color,
+    pub(crate) busy: Color,
+    pub(crate) do_not_disturb: Color,
+    // Other people's names get one of these, picked by a hash of the name
+    pub(crate) nicks: Vec<Color>,
+}
+
+impl Theme {
+    pub(crate) fn preset(preset: Preset) -> Self {
+        match preset {
+            // NOTE: the dark preset keeps the terminal's own colors where it can
+            Preset::Dark => Self {
+                preset,
+                text: Color::Reset,
+                background: Color::Reset,
+                border: Color::Reset,
+                input_border: Color::Green,
+                timestamp: Color::DarkGray,
+                system: Color::Blue,
+                error: Color::Red,
+                own: Color::Green,
+                hint: Color::DarkGray,
+                divider: Color::Red,
+                highlight: Color::Yellow,
+                mention: Color::Black,
+                mention_background: Color::Yellow,
+                match_background: Color::Cyan,
+                current_match_background: Color::LightGreen,
+                code: Color::White,
+                code_background: Color::DarkGray,
+                online: Color::Green,
+                away: Color::Yellow,
+                busy: Color::LightRed,
+                do_not_disturb: Color::Red,
+                nicks: vec![
+                    Color::Red,
+                    Color::Yellow,
+                    Color::Blue,
+                    Color::Magenta,
+                    Color::Cyan,
+                    Color::LightRed,
+                    Color::LightYellow,
+                    Color::LightBlue,
+                    Color::LightMagenta,
+                    Color::LightCyan,
+                ],
+            },
+            Preset::Light => Self {
+                preset,
+                text: Color::Black,
+                background: Color::White,
+                border: Color::DarkGray,
+                input_border: Color::Blue,
+                timestamp: Color::DarkGray,
+                system: Color::Blue,
+                error: Color::Red,
+                own: Color::Green,
+                hint: Color::DarkGray,
+                divider: Color::Red,
+                highlight: Color::Magenta,
+                mention: Color::Black,
+                mention_background: Color::LightYellow,
+                match_background: Color::LightCyan,
+                current_match_background: Color::LightGreen,
+                code: Color::Black,
+                code_background: Color::Gray,
+                online: Color::Green,
+                away: Color::Rgb(153, 85, 0),
+                busy: Color::Magenta,
+                do_not_disturb: Color::Red,
+                nicks: vec![
+                    Color::Red,
+                    Color::Blue,
+                    Color::Magenta,
+                    Color::Cyan,
+                    Color::Rgb(153, 85, 0),
+                    Color::Rgb(0, 102, 102),
+                    Color::Rgb(102, 0, 153),
+                    Color::Rgb(153, 0, 51),
+                ],
+            },
+            Preset::HighContrast => Self {
+                preset,
+                text: Color::White,
+                background: Color::Black,
+                border: Color::White,
+                input_border: Color::LightYellow,
+                timestamp: Color::White,
+                system: Color::LightCyan,
+                error: Color::LightRed,
+                own: Color::LightGreen,
+                hint: Color::White,
+                divider: Color::LightRed,
+                highlight: Color::LightYellow,
+                mention: Color::Black,
+                mention_background: Color::LightYellow,
+                match_background: Color::LightCyan,
+                current_match_background: Color::LightGreen,
+                code: Color::Black,
+                code_background: Color::White,
+                online: Color::LightGreen,
+                away: Color::LightYellow,
+                busy: Color::LightMagenta,
+                do_not_disturb: Color::LightRed,
+                nicks: vec![
+                    Color::LightRed,
+                    Color::LightYellow,
+                    Color::LightBlue,
+                    Color::LightMagenta,
+                    Color::LightCyan,
+                    Color::White,
+                ],
+            },
+        }
+    }
+
+    /// Sets one of the `KEYS`, unknown keys are checked for when the file is read
+    fn set(&mut self, key: &str, colors: &[Color]) {
+        let color = colors[0];
+        match key {
+            "text" => self.text = color,
+            "background" => self.background = color,
+            "border" => self.border = color,
+            "input_border" => self.input_border = color,
+            "timestamp" => self.timestamp = color,
+            "system" => self.system = color,
+            "error" => self.error = color,
+            "own" => self.own = color,
+            "hint" => self.hint = color,
+            "divider" => self.divider = color,
+            "highlight" => self.highlight = color,
+            "mention" => self.mention = color,
+            "mention_background" => self.mention_background = color,
+            "match_background" => self.match_background = color,
+            "current_match_background" => self.current_match_background = color,
+            "code" => self.code = color,
+            "code_background" => self.code_background = color,
+            "online" => self.online = color,
+            "away" => self.away = color,
+            "busy" => self.busy = color,
+            "do_not_disturb" => self.do_not_disturb = color,
+            "nicks" => self.nicks = colors.to_vec(),
+            _ => {}
+        }
+    }
+
+    /// What everything is drawn on
+    pub(crate) fn base(&self) -> Style {
+        Style::default().fg(self.text).bg(self.background)
+    }
+
+    pub(crate) fn mention_style(&self) -> Style {
+        Style::default()
+            .fg(self.mention)
+            .bg(self.mention_background)
+    }
+
+    pub(crate) fn match_style(&self, is_current: bool) -> Style {
+        let background = if is_current {
+            self.current_match_background
+        } else {
+            self.match_background
+        };
+        Style::default().fg(self.mention).bg(background)
+    }
+
+    pub(crate) fn code_style(&self) -> Style {
+        Style::default().fg(self.code).bg(self.code_background)
+    }
+
+    pub(crate) fn presence_color(&self, presence: Presence) -> Color {
+        match presence {
+            Presence::Online => self.online,
+            Presence::Away => self.away,
+            Presence::Busy => self.busy,
+            Presence::DoNotDisturb => self.do_not_disturb,
+        }
+    }
+
+    /// A color that stays the same for a name across sessions
+    pub(crate) fn nick_color(&self, name: &str) -> Color {
+        // NOTE: FNV-1a, unlike the std hasher it's stable between builds
+        let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        self.nicks[(hash % self.nicks.len() as u64) as usize]
+    }
+}
+
+/// The theme file, a preset and the colors overriding it, e.g.
+///
+/// ```text
+/// preset = light
+/// timestamp = #808080
+/// nicks = red, blue, magenta
+/// ```
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ThemeConfig {
+    preset: Preset,
+    overrides: Vec<(String, Vec<Color>)>,
+}
+
+impl ThemeConfig {
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("chad").join("theme"))
+    }
+
+    /// Reads the theme file from the user's config directory, the dark preset if there
+    /// is none
+    pub(crate) fn load() -> Result<Self> {
+        let Some(path) = Self::path().filter(|path| path.exists()) else {
+            return Ok(Self::default());
+        };
+        let source = std::fs::read_to_string(&path)?;
+        Self::parse(&source).with_context(|| format!("Invalid theme {}", path.display()))
+    }
+
+    fn parse(source: &str) -> Result<Self> {
+        let mut config = Self::default();
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| anyhow!("Line {}: expected key = value", number + 1))?;
+            if key == "preset" {
+                config.preset = Preset::parse(value)?;
+                continue;
+            }
+            if !KEYS.contains(&key) {
+                bail!("Line {}: unknown color {}", number + 1, key);
+            }
+            let colors = value
+                .split(',')
+                .map(|color| {
+                    Color::from_str(color.trim())
+                        .map_err(|_| anyhow!("Line {}: invalid color {}", number + 1, color))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            if colors.len() > 1 && key != "nicks" {
+                bail!("Line {}: {} takes a single color", number + 1, key);
+            }
+            config.overrides.push((key.to_string(), colors));
+        }
+        Ok(config)
+    }
+
+    /// The theme the file asks for
+    pub(crate) fn theme(&self) -> Theme {
+        self.theme_for(self.preset)
+    }
+
+    /// The preset, with the file's colors if it's the one the file is based on
+    pub(crate) fn theme_for(&self, preset: Preset) -> Theme {
+        let mut theme = Theme::preset(preset);
+        if preset == self.preset {
+            self.overrides
+                .iter()
+                .for_each(|(key, colors)| theme.set(key, colors));
+        }
+        theme
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_file() {
+        let config = ThemeConfig::parse(
+            "# comments are skipped\npreset = light\ntimestamp = #808080\nnicks = red, blue\n",
+        )
+        .unwrap();
+        let theme = config.theme();
+        assert_eq!(theme.preset, Preset::Light);
+        assert_eq!(theme.timestamp, Color::Rgb(128, 128, 128));
+        assert_eq!(theme.nicks, vec![Color::Red, Color::Blue]);
+        let config = ThemeConfig::parse("away = #808080").unwrap();
+        assert_eq!(
+            config.theme().presence_color(Presence::Away),
+            Color::Rgb(128, 128, 128)
+        );
+        // The overrides only go with the preset they were written for
+        let dark = config.theme_for(Preset::Dark);
+        assert_eq!(dark.timestamp, Color::DarkGray);
+
+        assert!(ThemeConfig::parse("bogus = red").is_err());
+        assert!(ThemeConfig::parse("own = red, blue").is_err());
+        assert!(ThemeConfig::parse("preset = sepia").is_err());
+
+        // Nick colors are stable
+        assert_eq!(theme.nick_color("alice"), theme.nick_color("alice"));
+    }
+}
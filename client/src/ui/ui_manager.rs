@@ -5,11 +5,17 @@ use std::time::Duration;
 use crate::state::action::Action;
 use crate::state::state::State;
 use anyhow::Result;
+use crossterm::event::DisableMouseCapture;
+use crossterm::event::EnableMouseCapture;
 use crossterm::event::Event;
 use crossterm::event::EventStream;
+use crossterm::event::KeyboardEnhancementFlags;
+use crossterm::event::PopKeyboardEnhancementFlags;
+use crossterm::event::PushKeyboardEnhancementFlags;
 use crossterm::execute;
 use crossterm::terminal::disable_raw_mode;
 use crossterm::terminal::enable_raw_mode;
+use crossterm::terminal::supports_keyboard_enhancement;
 use crossterm::terminal::Clear;
 use crossterm::terminal::ClearType;
 use crossterm::terminal::EnterAlternateScreen;
@@ -25,14 +31,19 @@ use tokio::sync::mpsc::UnboundedSender;
 use tokio::time::interval;
 
 use super::dispatch::Dispatcher;
+use super::theme::ThemeConfig;
 
 pub(crate) struct UiManager {
     action_tx: UnboundedSender<Action>,
+    theme_config: ThemeConfig,
 }
 
 impl UiManager {
-    pub(crate) fn new(action_tx: UnboundedSender<Action>) -> Self {
-        Self { action_tx }
+    pub(crate) fn new(action_tx: UnboundedSender<Action>, theme_config: ThemeConfig) -> Self {
+        Self {
+            action_tx,
+            theme_config,
+        }
     }
 
     pub(crate) async fn ui_loop(
@@ -45,14 +56,19 @@ impl UiManager {
             .recv()
             .await
             .expect("State channel closed unexpectedly right at the start");
-        let mut dispatcher = Dispatcher::new(self.action_tx.clone(), state);
+        let mut dispatcher =
+            Dispatcher::new(self.action_tx.clone(), state, self.theme_config.clone());
         let mut ticker = interval(Duration::from_millis(250));
         let mut crossterm_events = EventStream::new();
 
         loop {
             select! {
                 _ = ticker.tick() => {},
-                Some(Ok(Event::Key(event))) = crossterm_events.next().fuse() => dispatcher.handle_key_event(event),
+                Some(Ok(event)) = crossterm_events.next().fuse() => match event {
+                    Event::Key(key) => dispatcher.handle_key_event(key),
+                    Event::Mouse(mouse) => dispatcher.handle_mouse_event(mouse),
+                    _ => {}
+                },
                 Some(state) = state_rx.recv() => dispatcher.update(state),
                 _ = termination_rx.recv() => break,
             }
@@ -67,13 +83,28 @@ impl UiManager {
 fn enter_terminal_app() -> Result<Terminal<CrosstermBackend<Stdout>>> {
     install_panic_hook();
 
-    execute!(stdout(), EnterAlternateScreen, Clear(ClearType::All))?;
+    execute!(
+        stdout(),
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        Clear(ClearType::All)
+    )?;
     enable_raw_mode()?;
+    // NOTE: lets terminals that support it report Shift-Enter, to start a new line
+    if supports_keyboard_enhancement().unwrap_or(false) {
+        execute!(
+            stdout(),
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+        )?;
+    }
     Ok(Terminal::new(CrosstermBackend::new(stdout()))?)
 }
 
 fn exit_terminal_app() -> Result<()> {
-    execute!(stdout(), LeaveAlternateScreen)?;
+    if supports_keyboard_enhancement().unwrap_or(false) {
+        execute!(stdout(), PopKeyboardEnhancementFlags)?;
+    }
+    execute!(stdout(), DisableMouseCapture, LeaveAlternateScreen)?;
     disable_raw_mode()?;
     Ok(())
 }
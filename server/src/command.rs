@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+extern crate shared;
+use shared::message::{Chatter, Presence};
+
+use crate::moderation::{self, Role};
+use crate::store::Store;
+
+/// What a command gets to see of the server when it runs
+pub struct ServerInfo<'a> {
+    pub chatters: Vec<Chatter>,
+    // Where everyone online connected from
+    pub addresses: HashMap<String, IpAddr>,
+    pub uptime: Duration,
+    pub messages_relayed: u64,
+    pub store: &'a Store,
+    pub commands: &'a CommandRegistry,
+}
+
+pub struct CommandContext<'a> {
+    // Name of the client that issued the command
+    pub caller: &'a str,
+    // Only a caller that logged in with the name's password gets its role
+    pub caller_role: Role,
+    pub caller_authenticated: bool,
+    // Everything after the command name, trimmed
+    pub args: &'a str,
+    pub server: ServerInfo<'a>,
+    effects: Vec<Effect>,
+}
+
+impl<'a> CommandContext<'a> {
+    pub fn new(
+        caller: &'a str,
+        caller_role: Role,
+        caller_authenticated: bool,
+        args: &'a str,
+        server: ServerInfo<'a>,
+    ) -> Self {
+        Self {
+            caller,
+            caller_role,
+            caller_authenticated,
+            args,
+            server,
+            effects: Vec::new(),
+        }
+    }
+
+    pub fn is_online(&self, name: &str) -> bool {
+        self.server
+            .chatters
+            .iter()
+            .any(|c| c.name == name.as_bytes())
+    }
+
+    /// Asks the server to change its state once the command is done
+    pub fn apply(&mut self, effect: Effect) {
+        self.effects.push(effect);
+    }
+
+    pub fn into_effects(self) -> Vec<Effect> {
+        self.effects
+    }
+}
+
+pub enum BanTarget {
+    Name(String),
+    Ip(IpAddr),
+}
+
+/// Changes to the server state a command may ask for, commands only get to read the state
+pub enum Effect {
+    Kick { name: String, reason: String },
+    Mute(String),
+    Unmute(String),
+    Ban { target: BanTarget, reason: String },
+    Unban(BanTarget),
+    SetRole { name: String, role: Role },
+    SetPassword { name: String, password: String },
+}
+
+/// A "/name args" command typed into the chat. The reply goes back to the caller only, an
+/// error is shown to them as such
+pub trait ChatCommand: Send + Sync {
+    fn name(&self) -> &str;
+    fn help(&self) -> &str;
+    fn run(&self, context: &mut CommandContext) -> Result<String>;
+
+    /// The lowest role allowed to run the command
+    fn required_role(&self) -> Role {
+        Role::User
+    }
+}
+
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Box<dyn ChatCommand>>,
+}
+
+impl CommandRegistry {
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        // NOTE: Safety: the builtins have distinct names
+        registry.register(Commands).unwrap();
+        registry.register(Who).unwrap();
+        registry.register(Uptime).unwrap();
+        registry.register(Stats).unwrap();
+        registry.register(Password).unwrap();
+        moderation::register(&mut registry);
+        registry
+    }
+
+    pub fn register(&mut self, command: impl ChatCommand + 'static) -> Result<()> {
+        let name = command.name().to_string();
+        if self.commands.contains_key(&name) {
+            bail!("Command /{} is already registered", name);
+        }
+        self.commands.insert(name, Box::new(command));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn ChatCommand> {
+        self.commands.get(name).map(|c| c.as_ref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn ChatCommand> {
+        self.commands.values().map(|c| c.as_ref())
+    }
+}
+
+/// Splits "/name args" into the name and the arguments, `None` if the text is not a command.
+/// "/me" is left alone since emotes are regular chat messages, and "//" escapes the slash
+pub fn parse_command(msg: &str) -> Option<(&str, &str)> {
+    let line = msg.strip_prefix('/')?;
+    if line.starts_with('/') || line.starts_with("me ") {
+        return None;
+    }
+    let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    Some((name, args.trim()))
+}
+
+struct Commands;
+
+impl ChatCommand for Commands {
+    fn name(&self) -> &str {
+        "commands"
+    }
+
+    fn help(&self) -> &str {
+        "list the commands the server knows"
+    }
+
+    fn run(&self, context: &mut CommandContext) -> Result<String> {
+        Ok(context
+            .server
+            .commands
+            .iter()
+            .filter(|c| c.required_role() <= context.caller_role)
+            .map(|c| format!("/{} — {}", c.name(), c.help()))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+struct Who;
+
+impl ChatCommand for Who {
+    fn name(&self) -> &str {
+        "who"
+    }
+
+    fn help(&self) -> &str {
+        "list who is online, optionally only names starting with the argument"
+    }
+
+    fn run(&self, context: &mut CommandContext) -> Result<String> {
+        let mut names: Vec<String> = context
+            .server
+            .chatters
+            .iter()
+            .filter(|c| c.name.starts_with(context.args.as_bytes()))
+            .map(|c| {
+                let name = String::from_utf8_lossy(&c.name);
+                let you = if name == context.caller { " (you)" } else { "" };
+                match c.presence {
+                    Presence::Online => format!("{name}{you}"),
+                    presence => format!("{name}{you} ({presence})"),
+                }
+            })
+            .collect();
+        names.sort();
+        Ok(format!("Online ({}): {}", names.len(), names.join(", ")))
+    }
+}
+
+struct Uptime;
+
+impl ChatCommand for Uptime {
+    fn name(&self) -> &str {
+        "uptime"
+    }
+
+    fn help(&self) -> &str {
+        "how long the server has been running"
+    }
+
+    fn run(&self, context: &mut CommandContext) -> Result<String> {
+        let seconds = context.server.uptime.as_secs();
+        Ok(format!(
+            "Up for {}h {}m {}s",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ))
+    }
+}
+
+struct Stats;
+
+impl ChatCommand for Stats {
+    fn name(&self) -> &str {
+        "stats"
+    }
+
+    fn help(&self) -> &str {
+        "server wide numbers"
+    }
+
+    fn run(&self, context: &mut CommandContext) -> Result<String> {
+        Ok(format!(
+            "{} online, {} users ever, {} messages since the start",
+            context.server.chatters.len(),
+            context.server.store.registered_users(),
+            context.server.messages_relayed
+        ))
+    }
+}
+
+struct Password;
+
+impl ChatCommand for Password {
+    fn name(&self) -> &str {
+        "password"
+    }
+
+    fn help(&self) -> &str {
+        "protect your name with a password, /password <new password>"
+    }
+
+    fn run(&self, context: &mut CommandContext) -> Result<String> {
+        if context.args.is_empty() {
+            bail!("Usage: /password <new password>");
+        }
+        if context.server.store.has_password(context.caller) && !context.caller_authenticated {
+            bail!("Log in with the current password to change it");
+        }
+        context.apply(Effect::SetPassword {
+            name: context.caller.to_string(),
+            password: context.args.to_string(),
+        });
+        Ok("Password set, log in with it from now on".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("/who"), Some(("who", "")));
+        assert_eq!(parse_command("/roll  2d6 "), Some(("roll", "2d6")));
+        assert_eq!(parse_command("/me waves"), None);
+        assert_eq!(parse_command("//who"), None);
+        assert_eq!(parse_command("who"), None);
+    }
+
+    #[test]
+    fn test_register_twice() {
+        let mut registry = CommandRegistry::with_builtins();
+        assert!(registry.register(Who).is_err());
+        assert!(registry.get("uptime").is_some());
+        assert!(registry.get("nope").is_none());
+    }
+}
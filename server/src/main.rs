@@ -1,8 +1,22 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use tokio::{net::TcpListener, signal::ctrl_c};
 
 extern crate shared;
+mod command;
+mod moderation;
+mod rate_limit;
 mod server;
+mod store;
+
+use crate::command::CommandRegistry;
+use crate::moderation::Role;
+use crate::rate_limit::RateLimit;
+use crate::server::LagPolicy;
+use crate::store::Store;
+
+const STORE_PATH: &str = "chad.store";
+// Comma separated "name:password" pairs that are made operators on startup
+const OPERATORS_ENV: &str = "CHAD_OPERATORS";
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -10,6 +24,33 @@ async fn main() -> Result<()> {
     let ctrl_c = ctrl_c();
 
     println!("Serving at 127.0.0.1:8080");
-    server::run(tcp_listener, ctrl_c).await?;
+    let mut store = Store::open(STORE_PATH).await?;
+    if let Ok(operators) = std::env::var(OPERATORS_ENV) {
+        for operator in operators
+            .split(',')
+            .map(str::trim)
+            .filter(|o| !o.is_empty())
+        {
+            // NOTE: roles only take effect with a password, so an operator must have one
+            let (name, password) = operator
+                .split_once(':')
+                .ok_or(anyhow!("Expected name:password in {}", OPERATORS_ENV))?;
+            store.set_role(name, Role::Operator);
+            store.set_password(name, password.as_bytes());
+        }
+    }
+    // NOTE: custom commands get registered here, next to the builtins
+    let commands = CommandRegistry::with_builtins();
+    let rate_limit = RateLimit::from_env()?;
+    let lag_policy = LagPolicy::from_env()?;
+    server::run(
+        tcp_listener,
+        ctrl_c,
+        store,
+        commands,
+        rate_limit,
+        lag_policy,
+    )
+    .await?;
     Ok(())
 }
@@ -0,0 +1,263 @@
+use std::net::IpAddr;
+
+use anyhow::{bail, Result};
+
+use crate::command::{BanTarget, ChatCommand, CommandContext, CommandRegistry, Effect};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    #[default]
+    User,
+    Moderator,
+    Operator,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Moderator => "moderator",
+            Self::Operator => "operator",
+        }
+    }
+
+    pub fn parse(role: &str) -> Result<Self> {
+        match role {
+            "user" => Ok(Self::User),
+            "moderator" => Ok(Self::Moderator),
+            "operator" => Ok(Self::Operator),
+            unknown => bail!("Unknown role: {}", unknown),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+pub fn register(registry: &mut CommandRegistry) {
+    // NOTE: Safety: the moderation commands have distinct names, and are registered first
+    registry.register(Kick).unwrap();
+    registry.register(Mute).unwrap();
+    registry.register(Unmute).unwrap();
+    registry.register(Ban).unwrap();
+    registry.register(Unban).unwrap();
+    registry.register(SetRole).unwrap();
+}
+
+/// Splits the arguments into the target and the optional rest
+fn target_and_reason<'a>(context: &CommandContext<'a>, usage: &str) -> Result<(&'a str, &'a str)> {
+    if context.args.is_empty() {
+        bail!("Usage: {}", usage);
+    }
+    let (target, reason) = context
+        .args
+        .split_once(char::is_whitespace)
+        .unwrap_or((context.args, ""));
+    Ok((target, reason.trim()))
+}
+
+/// Moderators can't act on themselves, nor on anyone at their own level or above
+fn ensure_outranks(context: &CommandContext, target: &str) -> Result<()> {
+    if target == context.caller {
+        bail!("You can't do that to yourself");
+    }
+    let target_role = context.server.store.role(target);
+    if target_role >= context.caller_role {
+        bail!("Permission denied, {} is a {}", target, target_role);
+    }
+    Ok(())
+}
+
+fn parse_ban_target(target: &str) -> BanTarget {
+    match target.parse::<IpAddr>() {
+        Ok(ip) => BanTarget::Ip(ip),
+        Err(_) => BanTarget::Name(target.to_string()),
+    }
+}
+
+struct Kick;
+
+impl ChatCommand for Kick {
+    fn name(&self) -> &str {
+        "kick"
+    }
+
+    fn help(&self) -> &str {
+        "disconnect someone, /kick <name> [reason]"
+    }
+
+    fn required_role(&self) -> Role {
+        Role::Moderator
+    }
+
+    fn run(&self, context: &mut CommandContext) -> Result<String> {
+        let (name, reason) = target_and_reason(context, "/kick <name> [reason]")?;
+        if !context.is_online(name) {
+            bail!("{} is not online", name);
+        }
+        ensure_outranks(context, name)?;
+        context.apply(Effect::Kick {
+            name: name.to_string(),
+            reason: reason.to_string(),
+        });
+        Ok(format!("Kicked {name}"))
+    }
+}
+
+struct Mute;
+
+impl ChatCommand for Mute {
+    fn name(&self) -> &str {
+        "mute"
+    }
+
+    fn help(&self) -> &str {
+        "stop someone's messages from reaching the chat, /mute <name>"
+    }
+
+    fn required_role(&self) -> Role {
+        Role::Moderator
+    }
+
+    fn run(&self, context: &mut CommandContext) -> Result<String> {
+        let (name, _) = target_and_reason(context, "/mute <name>")?;
+        ensure_outranks(context, name)?;
+        if context.server.store.is_muted(name) {
+            bail!("{} is already muted", name);
+        }
+        context.apply(Effect::Mute(name.to_string()));
+        Ok(format!("Muted {name}"))
+    }
+}
+
+struct Unmute;
+
+impl ChatCommand for Unmute {
+    fn name(&self) -> &str {
+        "unmute"
+    }
+
+    fn help(&self) -> &str {
+        "let someone talk again, /unmute <name>"
+    }
+
+    fn required_role(&self) -> Role {
+        Role::Moderator
+    }
+
+    fn run(&self, context: &mut CommandContext) -> Result<String> {
+        let (name, _) = target_and_reason(context, "/unmute <name>")?;
+        ensure_outranks(context, name)?;
+        if !context.server.store.is_muted(name) {
+            bail!("{} is not muted", name);
+        }
+        context.apply(Effect::Unmute(name.to_string()));
+        Ok(format!("Unmuted {name}"))
+    }
+}
+
+struct Ban;
+
+impl ChatCommand for Ban {
+    fn name(&self) -> &str {
+        "ban"
+    }
+
+    fn help(&self) -> &str {
+        "keep a name or an IP address out, /ban <name|ip> [reason]"
+    }
+
+    fn required_role(&self) -> Role {
+        Role::Moderator
+    }
+
+    fn run(&self, context: &mut CommandContext) -> Result<String> {
+        let (target, reason) = target_and_reason(context, "/ban <name|ip> [reason]")?;
+        let reply = format!("Banned {target}");
+        let target = parse_ban_target(target);
+        match &target {
+            BanTarget::Name(name) => ensure_outranks(context, name)?,
+            // NOTE: everyone connected from the address gets kicked, so it takes outranking
+            // all of them
+            BanTarget::Ip(ip) => {
+                for (name, _) in context.server.addresses.iter().filter(|(_, a)| *a == ip) {
+                    ensure_outranks(context, name)?;
+                }
+            }
+        }
+        context.apply(Effect::Ban {
+            target,
+            reason: reason.to_string(),
+        });
+        Ok(reply)
+    }
+}
+
+struct Unban;
+
+impl ChatCommand for Unban {
+    fn name(&self) -> &str {
+        "unban"
+    }
+
+    fn help(&self) -> &str {
+        "lift a ban, /unban <name|ip>"
+    }
+
+    fn required_role(&self) -> Role {
+        Role::Moderator
+    }
+
+    fn run(&self, context: &mut CommandContext) -> Result<String> {
+        let (target, _) = target_and_reason(context, "/unban <name|ip>")?;
+        let reply = format!("Unbanned {target}");
+        let ban_target = parse_ban_target(target);
+        let banned = match &ban_target {
+            BanTarget::Name(name) => context.server.store.name_ban(name).is_some(),
+            BanTarget::Ip(ip) => context.server.store.ip_ban(ip).is_some(),
+        };
+        if !banned {
+            bail!("{} is not banned", target);
+        }
+        context.apply(Effect::Unban(ban_target));
+        Ok(reply)
+    }
+}
+
+struct SetRole;
+
+impl ChatCommand for SetRole {
+    fn name(&self) -> &str {
+        "role"
+    }
+
+    fn help(&self) -> &str {
+        "change someone's role, /role <name> <user|moderator|operator>"
+    }
+
+    fn required_role(&self) -> Role {
+        Role::Operator
+    }
+
+    fn run(&self, context: &mut CommandContext) -> Result<String> {
+        let usage = "Usage: /role <name> <user|moderator|operator>";
+        let Some((name, role)) = context.args.split_once(char::is_whitespace) else {
+            bail!(usage);
+        };
+        let role = Role::parse(role.trim()).map_err(|_| anyhow::anyhow!(usage))?;
+        ensure_outranks(context, name)?;
+        // NOTE: roles only take effect for clients logged in with a password, anyone can
+        // pick a name
+        if role > Role::User && !context.server.store.has_password(name) {
+            bail!("{} has to set a /password first", name);
+        }
+        context.apply(Effect::SetRole {
+            name: name.to_string(),
+            role,
+        });
+        Ok(format!("{name} is now a {role}"))
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::{Context, Result};
+use tokio::time::Instant;
+
+const BURST_ENV: &str = "CHAD_RATE_BURST";
+const PER_SECOND_ENV: &str = "CHAD_RATE_PER_SECOND";
+const MAX_STRIKES_ENV: &str = "CHAD_RATE_MAX_STRIKES";
+
+/// How fast a single connection may send messages
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    // Messages that can be sent at once after a pause
+    pub burst: u32,
+    // Messages per second that can be sustained
+    pub per_second: f64,
+    // Messages dropped in a row before the client is disconnected
+    pub max_strikes: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            burst: 10,
+            per_second: 2.0,
+            max_strikes: 20,
+        }
+    }
+}
+
+impl RateLimit {
+    /// The defaults, overridden by whichever of the environment variables are set
+    pub fn from_env() -> Result<Self> {
+        let mut limit = Self::default();
+        if let Ok(burst) = std::env::var(BURST_ENV) {
+            limit.burst = burst.parse().context(BURST_ENV)?;
+        }
+        if let Ok(per_second) = std::env::var(PER_SECOND_ENV) {
+            limit.per_second = per_second.parse().context(PER_SECOND_ENV)?;
+        }
+        if let Ok(max_strikes) = std::env::var(MAX_STRIKES_ENV) {
+            limit.max_strikes = max_strikes.parse().context(MAX_STRIKES_ENV)?;
+        }
+        Ok(limit)
+    }
+}
+
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token if there is one, returns whether the message may go through
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        self.last_refill = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            burst: 3,
+            per_second: 2.0,
+            max_strikes: 5,
+        });
+        let start = Instant::now();
+        assert!((0..3).all(|_| bucket.try_take(start)));
+        assert!(!bucket.try_take(start));
+        // Half a second buys exactly one more message
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+        // A long pause never refills past the burst
+        let later = start + Duration::from_secs(60);
+        assert!((0..3).all(|_| bucket.try_take(later)));
+        assert!(!bucket.try_take(later));
+    }
+}
@@ -1,23 +1,86 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::future::Future;
-use std::time::SystemTime;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, bail, Result};
 use bytes::Bytes;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::time::Instant;
 
 extern crate shared;
 use shared::connection::Connection;
-use shared::message::{Message, UserEnteredChat, UserLeftChat, WelcomeMessage, WhoIsInChat};
+use shared::message::{
+    is_room_name, ChatMessage, Chatter, DirectMessage, Disconnect, History, Message, Notice,
+    Presence, Reaction, ReactionKind, ReactionsUpdated, ReadMarker, RosterAdd, RosterRemove,
+    UserEnteredChat, UserLeftChat, WelcomeMessage, WhoIsInChat,
+};
+
+use crate::command::{
+    parse_command, BanTarget, CommandContext, CommandRegistry, Effect, ServerInfo,
+};
+use crate::moderation::Role;
+use crate::rate_limit::{RateLimit, TokenBucket};
+use crate::store::Store;
+
+// NOTE: emojis can be made of several code points, but nothing sane is longer than this
+const MAX_EMOJI_BYTES: usize = 32;
+const MAX_STATUS_TEXT_BYTES: usize = 64;
+// Inactivity after which an online client is automatically marked away
+const AWAY_AFTER: Duration = Duration::from_secs(5 * 60);
+// Messages addressed to a single client that may wait for its handler
+const MAILBOX_CAPACITY: usize = 32;
+// Recent messages replayed to clients when they log in, shared by all the rooms
+const HISTORY_CAPACITY: usize = 100;
+// Broadcasts a client may fall behind on before it starts missing them
+const BROADCAST_CAPACITY: usize = 64;
+const LAG_POLICY_ENV: &str = "CHAD_LAG_POLICY";
+// How often frequent store changes, like read markers, are written out
+const STORE_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+// How long a client that fell behind gets to take the history it missed
+const RESYNC_TIMEOUT: Duration = Duration::from_secs(10);
+// How long connection handlers get to say goodbye to their clients on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What happens to a client too slow to keep up with the broadcasts
+#[derive(Clone, Copy, Debug, Default)]
+pub enum LagPolicy {
+    Disconnect,
+    // Sends the recent history over again, so nothing is lost as long as it fits in there
+    #[default]
+    Resync,
+}
+
+impl LagPolicy {
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(LAG_POLICY_ENV).as_deref() {
+            Err(_) => Ok(Self::default()),
+            Ok("disconnect") => Ok(Self::Disconnect),
+            Ok("resync") => Ok(Self::Resync),
+            Ok(unknown) => bail!("Unknown {}: {}", LAG_POLICY_ENV, unknown),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Client {
     status: ClientStatus,
     name: String,
     connected_at: chrono::NaiveDateTime,
+    address: IpAddr,
     messages_sent: u64,
+    presence: Presence,
+    status_text: String,
+    // Whether the client logged in with the name's password, it only gets its role then
+    authenticated: bool,
+    // Messages addressed to this client only, delivered by its connection handler
+    mailbox: mpsc::Sender<Message>,
+    // The reason to close the connection with, bypasses the mailbox so it can't be dropped
+    kick: Arc<watch::Sender<Option<String>>>,
 }
 
 #[derive(Clone, Debug)]
@@ -27,12 +90,24 @@ pub enum ClientStatus {
 }
 
 impl Client {
-    fn new(name: String, connected_at: chrono::NaiveDateTime) -> Self {
+    fn new(
+        name: String,
+        connected_at: chrono::NaiveDateTime,
+        address: IpAddr,
+        mailbox: mpsc::Sender<Message>,
+        kick: Arc<watch::Sender<Option<String>>>,
+    ) -> Self {
         Self {
             status: ClientStatus::Online,
             name,
             connected_at,
+            address,
             messages_sent: 0,
+            presence: Presence::Online,
+            status_text: String::new(),
+            authenticated: false,
+            mailbox,
+            kick,
         }
     }
 
@@ -41,15 +116,78 @@ impl Client {
             status: ClientStatus::Offline,
             name: self.name,
             connected_at: self.connected_at,
+            address: self.address,
             messages_sent: self.messages_sent,
+            presence: self.presence,
+            status_text: self.status_text,
+            authenticated: self.authenticated,
+            mailbox: self.mailbox,
+            kick: self.kick,
         }
     }
 
-    fn increment_messages(&mut self) {
-        self.messages_sent += 1;
+    fn chatter(&self) -> Chatter {
+        Chatter::new(
+            self.name.clone().into(),
+            self.presence,
+            self.status_text.clone().into(),
+            self.connected_at.and_utc().timestamp_millis() as u64,
+            self.messages_sent,
+        )
     }
 }
 
+/// Things clients do that the server has to keep track of
+#[derive(Debug)]
+pub enum ClientEvent {
+    // The server either takes the client in, or says why not
+    Login {
+        client: Client,
+        password: Bytes,
+        accepted: oneshot::Sender<Result<(), String>>,
+    },
+    Reaction(Reaction),
+    ReadMarker {
+        name: String,
+        message_id: u64,
+    },
+    DirectMessage(DirectMessage),
+    Command {
+        caller: String,
+        name: String,
+        args: String,
+    },
+    // The client missed broadcasts and needs the recent history of its rooms again
+    Resync {
+        rooms: HashSet<Bytes>,
+        reply: oneshot::Sender<History>,
+    },
+    // The client joined a room and needs its recent history
+    RoomHistory {
+        name: String,
+        room: Bytes,
+    },
+}
+
+/// What every connection handler shares with the server
+#[derive(Clone)]
+struct HandlerContext {
+    // Handlers subscribe to the shutdown announcement with it
+    notify_shutdown: broadcast::Sender<()>,
+    client_status_sender: mpsc::Sender<Client>,
+    client_message_sender: broadcast::Sender<Message>,
+    client_event_sender: mpsc::Sender<ClientEvent>,
+    next_message_id: Arc<AtomicU64>,
+    // Held while a message gets its id and is broadcast, so clients get the ids in order
+    relay_lock: Arc<Mutex<()>>,
+    // Names whose chat messages are dropped, kept up to date by the server
+    muted: Arc<RwLock<HashSet<String>>>,
+    rate_limit: RateLimit,
+    lag_policy: LagPolicy,
+    // NOTE: never used, the server knows every handler is done once all clones are dropped
+    _shutdown_complete: mpsc::Sender<()>,
+}
+
 struct Shutdown {
     shutdown_announced: bool,
     shutdown_receiver: broadcast::Receiver<()>,
@@ -83,11 +221,23 @@ where
     R: AsyncRead + Unpin,
 {
     connection: Connection<W, R>,
+    address: IpAddr,
+    context: HandlerContext,
     shutdown: Shutdown,
-    client_status_sender: mpsc::Sender<Client>,
-    client_message_sender: broadcast::Sender<Message>,
     client_message_receiver: broadcast::Receiver<Message>,
+    mailbox_sender: mpsc::Sender<Message>,
+    mailbox_receiver: mpsc::Receiver<Message>,
+    kick_sender: Arc<watch::Sender<Option<String>>>,
+    kick_receiver: watch::Receiver<Option<String>>,
     client: Option<Client>,
+    last_activity: Instant,
+    // Whether the away presence was set by inactivity rather than by the user
+    auto_away: bool,
+    rate_limiter: TokenBucket,
+    // Messages dropped in a row for going over the rate limit
+    strikes: u32,
+    // Rooms joined on top of the main chat, whose messages are let through
+    rooms: HashSet<Bytes>,
 }
 
 impl<W, R> ConnectionHandler<W, R>
@@ -95,78 +245,299 @@ where
     W: AsyncWrite + Unpin,
     R: AsyncRead + Unpin,
 {
-    fn new(
-        connection: Connection<W, R>,
-        shutdown: Shutdown,
-        client_status_sender: mpsc::Sender<Client>,
-        client_message_sender: broadcast::Sender<Message>,
-        client_message_receiver: broadcast::Receiver<Message>,
-    ) -> Self {
+    fn new(connection: Connection<W, R>, address: IpAddr, context: HandlerContext) -> Self {
+        let (mailbox_sender, mailbox_receiver) = mpsc::channel(MAILBOX_CAPACITY);
+        let (kick_sender, kick_receiver) = watch::channel(None);
         Self {
             connection,
-            shutdown,
-            client_status_sender,
-            client_message_sender,
-            client_message_receiver,
+            address,
+            shutdown: Shutdown::new(context.notify_shutdown.subscribe()),
+            client_message_receiver: context.client_message_sender.subscribe(),
+            rate_limiter: TokenBucket::new(context.rate_limit),
+            context,
+            mailbox_sender,
+            mailbox_receiver,
+            kick_sender: Arc::new(kick_sender),
+            kick_receiver,
             client: None,
+            last_activity: Instant::now(),
+            auto_away: false,
+            strikes: 0,
+            rooms: HashSet::new(),
         }
     }
 
+    /// Records user activity, bringing the client back online if it was marked away
+    /// automatically
+    async fn touch(&mut self) -> Result<()> {
+        self.last_activity = Instant::now();
+        if !self.auto_away {
+            return Ok(());
+        }
+        self.auto_away = false;
+        if let Some(client) = self.client.as_mut() {
+            client.presence = Presence::Online;
+            self.context
+                .client_status_sender
+                .send(client.clone())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Says goodbye to the client and lets the server know it is gone
+    async fn disconnect(&mut self, reason: Disconnect) -> Result<()> {
+        self.connection
+            .write_frame(Message::Disconnect(reason).into_frame())
+            .await?;
+        if let Some(client) = self.client.take() {
+            self.context
+                .client_status_sender
+                .send(client.mark_offline())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Whether the message is over the rate limit. Only what the user does counts, read
+    /// markers and logging in and out are always let through
+    fn is_flooding(&mut self, message: &Message) -> bool {
+        let limited = matches!(
+            message,
+            Message::ChatMessage(_)
+                | Message::Reaction(_)
+                | Message::Typing(_)
+                | Message::SetStatus(_)
+                | Message::DirectMessage(_)
+                | Message::JoinRoom(_)
+                | Message::PartRoom(_)
+        );
+        if !limited {
+            return false;
+        }
+        if self.rate_limiter.try_take(Instant::now()) {
+            self.strikes = 0;
+            return false;
+        }
+        true
+    }
+
+    /// Drops a message over the rate limit, returns whether the client got disconnected for it
+    async fn strike(&mut self) -> Result<bool> {
+        self.strikes += 1;
+        if self.strikes >= self.context.rate_limit.max_strikes {
+            if let Some(client) = self.client.as_ref() {
+                eprintln!("Disconnecting {} for flooding", client.name);
+            }
+            self.disconnect(Disconnect::new("Disconnected for flooding".into()))
+                .await?;
+            return Ok(true);
+        }
+        // NOTE: warn once per streak, replying to every dropped message would flood back
+        if self.strikes == 1 {
+            let notice = Notice::error("You are sending messages too fast, slow down".into());
+            self.connection
+                .write_frame(Message::Notice(notice).into_frame())
+                .await?;
+        }
+        Ok(false)
+    }
+
+    /// Deals with broadcasts lost for being too slow, returns whether the client got
+    /// disconnected for it
+    async fn fell_behind(&mut self, missed: u64) -> Result<bool> {
+        let Some(client) = self.client.as_ref() else {
+            // NOTE: nothing is lost before logging in, the history comes with the login
+            return Ok(false);
+        };
+        eprintln!("{} fell behind by {} messages", client.name, missed);
+        match self.context.lag_policy {
+            LagPolicy::Disconnect => {
+                let reason = format!("Too slow to keep up, missed {missed} messages");
+                self.disconnect(Disconnect::new(reason.into())).await?;
+                Ok(true)
+            }
+            LagPolicy::Resync => {
+                let (reply, history) = oneshot::channel();
+                self.context
+                    .client_event_sender
+                    .send(ClientEvent::Resync {
+                        rooms: self.rooms.clone(),
+                        reply,
+                    })
+                    .await?;
+                // NOTE: the history is written here rather than through the mailbox, so that it
+                // can't be dropped for being too much at once
+                let notice =
+                    Notice::info(format!("You missed {missed} messages, catching up").into());
+                let catch_up = async {
+                    let history = history.await?;
+                    self.connection
+                        .write_frame(Message::Notice(notice).into_frame())
+                        .await?;
+                    self.connection
+                        .write_frame(Message::History(history).into_frame())
+                        .await
+                };
+                if let Ok(Ok(())) = tokio::time::timeout(RESYNC_TIMEOUT, catch_up).await {
+                    return Ok(false);
+                }
+                // NOTE: a client that can't even take the history is stuck, there is no point in
+                // saying goodbye over the same connection
+                if let Some(client) = self.client.take() {
+                    eprintln!("Disconnecting {}, it couldn't catch up", client.name);
+                    self.context
+                        .client_status_sender
+                        .send(client.mark_offline())
+                        .await?;
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// Whether a broadcast chat message is meant for this client
+    fn is_listening(&self, message: &ChatMessage) -> bool {
+        message.room.is_empty() || self.rooms.contains(&message.room)
+    }
+
+    async fn notify(&mut self, notice: Notice) -> Result<()> {
+        self.connection
+            .write_frame(Message::Notice(notice).into_frame())
+            .await
+    }
+
+    fn may_go_away(&self) -> bool {
+        self.client
+            .as_ref()
+            .is_some_and(|c| c.presence == Presence::Online)
+    }
+
     async fn handle(&mut self) -> Result<()> {
         while !self.shutdown.shutdown_announced() {
+            let away_deadline = self.last_activity + AWAY_AFTER;
             let maybe_frame = tokio::select! {
-                frame = self.connection.read_frame() => frame,
+                // NOTE: the mailbox must be drained before broadcasts, so that the roster
+                // snapshot is applied before any delta that was broadcast after it
+                biased;
+
                 _ = self.shutdown.recv_shutdown() => {
+                    // NOTE: the server doesn't track clients anymore, only the client is told
+                    let reason = Disconnect::new("The server is shutting down".into());
+                    self.connection.write_frame(Message::Disconnect(reason).into_frame()).await?;
                     return Ok(())
                 }
+                Ok(()) = self.kick_receiver.changed() => {
+                    let reason = self.kick_receiver.borrow().clone().unwrap_or_default();
+                    self.disconnect(Disconnect::new(reason.into())).await?;
+                    return Ok(());
+                }
+                frame = self.connection.read_frame() => frame,
+                Some(message) = self.mailbox_receiver.recv() => {
+                    self.connection.write_frame(message.into_frame()).await?;
+                    continue;
+                }
+                _ = tokio::time::sleep_until(away_deadline), if self.may_go_away() => {
+                    // NOTE: Safety: may_go_away only holds for a logged in client
+                    let client = self.client.as_mut().unwrap();
+                    client.presence = Presence::Away;
+                    self.auto_away = true;
+                    self.context.client_status_sender.send(client.clone()).await?;
+                    continue;
+                }
                 // TODO: doing via continue for now for a quick and dirty solution
                 broadcasted_message = self.client_message_receiver.recv() => {
                     println!("Receiving broadcasted_message");
-                    if let Ok(message) = broadcasted_message {
-                        match message {
-                            Message::ChatMessage(_) => {
-                                //let mut dbg_msg = BufWriter::new(Vec::new());
-                                //write_frame_into(&mut dbg_msg, message.clone().into_frame()).await?;
-                                //println!("Sending: {:?}", Bytes::copy_from_slice(dbg_msg.get_ref().as_slice()));
-                                self.connection.write_frame(message.into_frame()).await?;
-                            },
-                            Message::UserEnteredChat(_) => {
-                                self.connection.write_frame(message.into_frame()).await?;
+                    // NOTE: everything before logging in comes with the login, the client
+                    // expects the welcome first
+                    if self.client.is_none() {
+                        if let Err(broadcast::error::RecvError::Closed) = broadcasted_message {
+                            eprintln!("Error receiving broadcast: the server is gone");
+                        }
+                        continue;
+                    }
+                    match broadcasted_message {
+                        Ok(message) => match message {
+                            Message::ChatMessage(ref msg) => {
+                                if self.is_listening(msg) {
+                                    self.connection.write_frame(message.into_frame()).await?;
+                                }
                             }
-                            Message::UserLeftChat(_) => {
+                            Message::UserEnteredChat(_)
+                            | Message::UserLeftChat(_)
+                            | Message::RosterAdd(_)
+                            | Message::RosterRemove(_)
+                            | Message::ReactionsUpdated(_) => {
                                 self.connection.write_frame(message.into_frame()).await?;
                             }
-                            Message::WhoIsInChat(_) => {
-                                self.connection.write_who_is_in_chat(message.into_frame()).await?;
+                            Message::Typing(ref typing) => {
+                                let is_own = self
+                                    .client
+                                    .as_ref()
+                                    .is_some_and(|c| c.name.as_bytes() == typing.name);
+                                if !is_own {
+                                    self.connection.write_frame(message.into_frame()).await?;
+                                }
                             }
                             unexpected => {
                                 eprintln!("Expected a chat message, got {:?}", unexpected)
                             },
+                        },
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            if self.fell_behind(missed).await? {
+                                return Ok(());
+                            }
                         }
-                    } else {
-                        eprintln!("Error receiving broadcast: {:?}", broadcasted_message);
+                        Err(e) => eprintln!("Error receiving broadcast: {:?}", e),
                     }
                     continue;
                 }
             };
 
             // TODO: refactor message handling once it is parsed and verified
-            match Message::from_frame(maybe_frame?) {
+            let message = Message::from_frame(maybe_frame?);
+            if message.as_ref().is_ok_and(|m| self.is_flooding(m)) {
+                if self.strike().await? {
+                    return Ok(());
+                }
+                continue;
+            }
+            match message {
                 Ok(msg) => match msg {
                     Message::Login(msg) => {
+                        if self.client.is_some() {
+                            let notice = Notice::error("You are logged in already".into());
+                            self.notify(notice).await?;
+                            continue;
+                        }
                         let now_timestamp = SystemTime::now()
                             .duration_since(SystemTime::UNIX_EPOCH)?
                             .as_millis();
-                        self.client = Some(Client::new(
+                        let client = Client::new(
                             String::from_utf8(msg.name.to_vec())?,
                             chrono::NaiveDateTime::from_timestamp_millis(now_timestamp.try_into()?)
                                 .ok_or(anyhow!("The clock might've gone backwards"))?,
-                        ));
-                        // NOTE: Safety: the client is initialized just before unwrapping, hence
-                        // it's safe
-                        self.client_status_sender
-                            .send(self.client.as_ref().unwrap().clone())
+                            self.address,
+                            self.mailbox_sender.clone(),
+                            self.kick_sender.clone(),
+                        );
+                        let (accepted_sender, accepted) = oneshot::channel();
+                        self.context
+                            .client_event_sender
+                            .send(ClientEvent::Login {
+                                client: client.clone(),
+                                password: msg.password,
+                                accepted: accepted_sender,
+                            })
                             .await?;
+                        if let Err(reason) = accepted.await? {
+                            let reason = Disconnect::new(reason.into());
+                            self.connection
+                                .write_frame(Message::Disconnect(reason).into_frame())
+                                .await?;
+                            return Ok(());
+                        }
+                        self.client = Some(client);
 
                         let message =
                             Message::WelcomeMessage(WelcomeMessage::new("Welcome to chad!".into()))
@@ -174,23 +545,182 @@ where
                         self.connection.write_frame(message).await?;
                     }
                     Message::Logout(_) => {
-                        // NOTE: Safety: unwrap below should never panic? LUL
-                        let logged_out_client = self.client.take().unwrap().mark_offline();
-                        self.client_status_sender.send(logged_out_client).await?;
+                        // NOTE: logging out before logging in just closes the connection
+                        let Some(client) = self.client.take() else {
+                            return Ok(());
+                        };
+                        self.context
+                            .client_status_sender
+                            .send(client.mark_offline())
+                            .await?;
                         return Ok(());
                     }
-                    Message::ChatMessage(msg) => {
-                        self.client_message_sender
+                    Message::ChatMessage(mut msg) => {
+                        self.touch().await?;
+                        let Some(client) = self.client.as_ref() else {
+                            bail!("Chatting before logging in");
+                        };
+                        let text = String::from_utf8_lossy(&msg.msg).to_string();
+                        if let Some((name, args)) = parse_command(&text) {
+                            self.context
+                                .client_event_sender
+                                .send(ClientEvent::Command {
+                                    caller: client.name.clone(),
+                                    name: name.to_string(),
+                                    args: args.to_string(),
+                                })
+                                .await?;
+                            continue;
+                        }
+                        // NOTE: Safety: the lock is never held across an await or a panic
+                        if self.context.muted.read().unwrap().contains(&client.name) {
+                            self.notify(Notice::error("You are muted".into())).await?;
+                            continue;
+                        }
+                        if !self.is_listening(&msg) {
+                            let room = String::from_utf8_lossy(&msg.room);
+                            let notice =
+                                Notice::error(format!("Join {room} before talking there").into());
+                            self.notify(notice).await?;
+                            continue;
+                        }
+                        if msg.msg.starts_with(b"//") {
+                            msg.msg = msg.msg.slice(1..);
+                        }
+                        msg.tag_mentions();
+                        // NOTE: clients drop messages older than the newest one they have, so
+                        // no other handler may broadcast between taking an id and sending
+                        let _relaying = self.context.relay_lock.lock().unwrap();
+                        msg.id = self.context.next_message_id.fetch_add(1, Ordering::Relaxed);
+                        // NOTE: the server counts the message for the roster as it relays it
+                        self.context
+                            .client_message_sender
                             .send(Message::ChatMessage(msg))
                             .map_err(|_| anyhow!("All receivers dropped the handle"))?;
-                        // TODO: proooobably not safe to unwrap here, should be if-let with
-                        // handling instead
-                        self.client.as_mut().unwrap().increment_messages();
+                    }
+                    Message::Reaction(mut reaction) => {
+                        let Some(client) = self.client.as_ref() else {
+                            bail!("Reacting before logging in");
+                        };
+                        if reaction.emoji.is_empty() || reaction.emoji.len() > MAX_EMOJI_BYTES {
+                            eprintln!("Ignoring a malformed reaction from {}", client.name);
+                            continue;
+                        }
+                        // NOTE: the name on the wire is not trusted, reactions are counted per
+                        // connected client
+                        reaction.name = client.name.clone().into();
+                        self.context
+                            .client_event_sender
+                            .send(ClientEvent::Reaction(reaction))
+                            .await?;
+                        self.touch().await?;
+                    }
+                    Message::Typing(mut typing) => {
+                        let Some(client) = self.client.as_ref() else {
+                            bail!("Typing before logging in");
+                        };
+                        typing.name = client.name.clone().into();
+                        // NOTE: nobody may be listening, which is fine for an ephemeral message
+                        let _ = self
+                            .context
+                            .client_message_sender
+                            .send(Message::Typing(typing));
+                        self.touch().await?;
+                    }
+                    Message::SetStatus(status) => {
+                        let Some(client) = self.client.as_mut() else {
+                            bail!("Setting a status before logging in");
+                        };
+                        let Ok(status_text) = String::from_utf8(status.status_text.to_vec()) else {
+                            let notice = Notice::error("The status text is not valid UTF-8".into());
+                            self.connection
+                                .write_frame(Message::Notice(notice).into_frame())
+                                .await?;
+                            continue;
+                        };
+                        if status_text.len() > MAX_STATUS_TEXT_BYTES {
+                            eprintln!("Ignoring an oversized status from {}", client.name);
+                            continue;
+                        }
+                        client.presence = status.presence;
+                        client.status_text = status_text;
+                        self.context
+                            .client_status_sender
+                            .send(client.clone())
+                            .await?;
+                        self.auto_away = false;
+                        self.last_activity = Instant::now();
+                    }
+                    Message::ReadMarker(marker) => {
+                        let Some(client) = self.client.as_ref() else {
+                            bail!("Reading messages before logging in");
+                        };
+                        self.context
+                            .client_event_sender
+                            .send(ClientEvent::ReadMarker {
+                                name: client.name.clone(),
+                                message_id: marker.message_id,
+                            })
+                            .await?;
+                    }
+                    Message::DirectMessage(mut message) => {
+                        let Some(client) = self.client.as_ref() else {
+                            bail!("Sending a direct message before logging in");
+                        };
+                        message.from = client.name.clone().into();
+                        self.context
+                            .client_event_sender
+                            .send(ClientEvent::DirectMessage(message))
+                            .await?;
+                        self.touch().await?;
+                    }
+                    Message::JoinRoom(join) => {
+                        let Some(client) = self.client.as_ref() else {
+                            bail!("Joining a room before logging in");
+                        };
+                        let room = String::from_utf8_lossy(&join.room).to_string();
+                        if !is_room_name(&room) {
+                            let notice = Notice::error(
+                                format!("{room} is not a room name, try #{room}").into(),
+                            );
+                            self.notify(notice).await?;
+                            continue;
+                        }
+                        if !self.rooms.insert(join.room.clone()) {
+                            self.notify(Notice::error(format!("You are already in {room}").into()))
+                                .await?;
+                            continue;
+                        }
+                        self.context
+                            .client_event_sender
+                            .send(ClientEvent::RoomHistory {
+                                name: client.name.clone(),
+                                room: join.room,
+                            })
+                            .await?;
+                        self.touch().await?;
+                    }
+                    Message::PartRoom(part) => {
+                        let room = String::from_utf8_lossy(&part.room).to_string();
+                        let notice = if self.rooms.remove(&part.room) {
+                            Notice::info(format!("You left {room}").into())
+                        } else {
+                            Notice::error(format!("You are not in {room}").into())
+                        };
+                        self.notify(notice).await?;
                     }
                     Message::WelcomeMessage(_)
                     | Message::UserEnteredChat(_)
                     | Message::UserLeftChat(_)
-                    | Message::WhoIsInChat(_) => bail!("We are hijacked, aborting immediately"),
+                    | Message::WhoIsInChat(_)
+                    | Message::RosterAdd(_)
+                    | Message::RosterRemove(_)
+                    | Message::History(_)
+                    | Message::Notice(_)
+                    | Message::Disconnect(_)
+                    | Message::ReactionsUpdated(_) => {
+                        bail!("We are hijacked, aborting immediately")
+                    }
                 },
                 Err(e) => {
                     eprintln!("Protocol error: {}", e);
@@ -206,17 +736,28 @@ pub struct Server {
     notify_shutdown: broadcast::Sender<()>,
     shutdown_complete: mpsc::Sender<()>,
     client_status_reciever: mpsc::Receiver<Client>,
+    client_event_receiver: mpsc::Receiver<ClientEvent>,
     clients_connected_cnt: u64,
-    clients_connected: HashSet<Bytes>,
+    clients_connected: HashMap<String, Client>,
+    next_message_id: Arc<AtomicU64>,
+    // message id -> emoji -> names of everyone who reacted with it
+    reactions: HashMap<u64, BTreeMap<Bytes, HashSet<Bytes>>>,
+    history: VecDeque<ChatMessage>,
+    messages_relayed: u64,
+    store: Store,
+    // Whether the store has changes that are waiting for the next flush
+    store_dirty: bool,
+    commands: CommandRegistry,
+    started_at: Instant,
+    // Shared with the connection handlers, mirrors the store
+    muted: Arc<RwLock<HashSet<String>>>,
 }
 
 impl Server {
-    pub async fn run(
-        &mut self,
-        client_status_sender: mpsc::Sender<Client>,
-        notify_shutdown: broadcast::Sender<()>,
-        client_message_sender: broadcast::Sender<Message>,
-    ) -> Result<()> {
+    async fn run(&mut self, context: HandlerContext) -> Result<()> {
+        let client_message_sender = context.client_message_sender.clone();
+        let mut history_receiver = client_message_sender.subscribe();
+        let mut store_flush = tokio::time::interval(STORE_FLUSH_INTERVAL);
         loop {
             let (socket, address) = tokio::select! {
                 conn = self.tcp_listener.accept() => conn?,
@@ -224,33 +765,31 @@ impl Server {
                     if let Some(client) = client_connected {
                         match client.status {
                             ClientStatus::Online => {
-                                self.clients_connected_cnt += 1;
-                                println!("New client connected: {:?}", client);
-                                let _ = client_message_sender.send(Message::UserEnteredChat(UserEnteredChat::new(
-                                    format!("{} joined the chat!", client.name).into(),
-                                    client.name.clone().into()
-                                )));
-                                self.clients_connected.insert(client.name.into());
-                                let _ = client_message_sender.send(
-                                    Message::WhoIsInChat(WhoIsInChat::new(
-                                        Vec::from_iter(self.clients_connected.clone())
-                                    ))
-                                );
-                                println!("Current clients connected: {:?}", self.clients_connected);
+                                // NOTE: a logged in client only changes its status, the stats are
+                                // counted here
+                                let Some(connected) = self.clients_connected.get_mut(&client.name) else {
+                                    continue;
+                                };
+                                let status_changed = connected.presence != client.presence
+                                    || connected.status_text != client.status_text;
+                                connected.presence = client.presence;
+                                connected.status_text = client.status_text;
+                                if status_changed {
+                                    let _ = client_message_sender.send(Message::RosterAdd(RosterAdd::new(connected.chatter())));
+                                }
                             }
                             ClientStatus::Offline => {
-                                // TODO: send UserLeftChatMessages (not imlemented yet)
+                                if self.clients_connected.remove(&client.name).is_none() {
+                                    continue;
+                                }
                                 self.clients_connected_cnt -= 1;
-                                self.clients_connected.remove(&Bytes::copy_from_slice(client.name.as_bytes()));
                                 let _ = client_message_sender.send(Message::UserLeftChat(UserLeftChat::new(
                                     format!("{} left the chat!", client.name).into(),
                                     client.name.clone().into()
                                 )));
-                                let _ = client_message_sender.send(
-                                    Message::WhoIsInChat(WhoIsInChat::new(
-                                        Vec::from_iter(self.clients_connected.clone())
-                                    ))
-                                );
+                                let _ = client_message_sender.send(Message::RosterRemove(RosterRemove::new(
+                                    client.name.clone().into()
+                                )));
                                 println!("Client {:?} disconnected", client);
                             }
                         }
@@ -259,54 +798,448 @@ impl Server {
                     }
                         continue;
                 }
+                Some(event) = self.client_event_receiver.recv() => {
+                    match event {
+                        ClientEvent::Login { client, password, accepted } => {
+                            let login = self.log_in(client, &password, &client_message_sender);
+                            let _ = accepted.send(login);
+                        }
+                        ClientEvent::Reaction(reaction) => {
+                            if let Some(update) = self.apply_reaction(reaction) {
+                                let _ = client_message_sender.send(Message::ReactionsUpdated(update));
+                            }
+                        }
+                        ClientEvent::DirectMessage(message) => self.route_direct_message(message),
+                        ClientEvent::Command { caller, name, args } => self.run_command(&caller, &name, &args).await,
+                        ClientEvent::Resync { rooms, reply } => {
+                            let _ = reply.send(self.history_of(&rooms));
+                        }
+                        ClientEvent::RoomHistory { name, room } => {
+                            self.send_to(&name, Message::Notice(Notice::info(
+                                format!("You joined {}", String::from_utf8_lossy(&room)).into()
+                            )));
+                            // NOTE: the main chat is shown already, only the room's messages are new
+                            let messages = self.history.iter().filter(|m| m.room == room).cloned().collect();
+                            self.send_to(&name, Message::History(History::new(messages)));
+                        }
+                        ClientEvent::ReadMarker { name, message_id } => {
+                            // NOTE: markers move with every message read, so they are only
+                            // written out on the next flush
+                            if message_id < self.next_message_id.load(Ordering::Relaxed)
+                                && self.store.set_read_marker(&name, message_id)
+                            {
+                                self.store_dirty = true;
+                            }
+                        }
+                    }
+                    continue;
+                }
+                _ = store_flush.tick(), if self.store_dirty => {
+                    self.save_store().await;
+                    continue;
+                }
+                Ok(Message::ChatMessage(message)) = history_receiver.recv() => {
+                    self.store.set_last_message_id(message.id);
+                    // NOTE: written out with the next flush, like the read markers
+                    self.store_dirty = true;
+                    // NOTE: clients keep track of the stats on their own, the roster only needs
+                    // them right for snapshots
+                    if let Some(client) = self.clients_connected.get_mut(&*String::from_utf8_lossy(&message.name)) {
+                        client.messages_sent += 1;
+                    }
+                    if self.history.len() == HISTORY_CAPACITY {
+                        self.history.pop_front();
+                    }
+                    self.history.push_back(message);
+                    self.messages_relayed += 1;
+                    continue;
+                }
             };
 
+            if let Some(reason) = self.store.ip_ban(&address.ip()) {
+                println!("Refused connection from banned {}: {}", address, reason);
+                continue;
+            }
             println!("Accepted connection from {}", address);
-            Self::spawn_handler_thread(
-                socket,
-                notify_shutdown.subscribe(),
-                client_status_sender.clone(),
-                client_message_sender.clone(),
-            )
+            Self::spawn_handler_thread(socket, address.ip(), context.clone());
         }
     }
 
-    fn spawn_handler_thread(
-        socket: TcpStream,
-        notify_shutdown_reciever: broadcast::Receiver<()>,
-        client_status_sender: mpsc::Sender<Client>,
-        client_message_sender: broadcast::Sender<Message>,
-    ) {
-        let (read_half, write_half) = socket.into_split();
-        let mut handler = ConnectionHandler::new(
-            Connection::new(read_half, write_half),
-            Shutdown::new(notify_shutdown_reciever),
-            client_status_sender,
-            client_message_sender.clone(),
-            client_message_sender.subscribe(),
+    /// Takes the client in unless the name is taken, banned, or the password is wrong. The
+    /// error is the reason given to the client
+    fn log_in(
+        &mut self,
+        mut client: Client,
+        password: &[u8],
+        client_message_sender: &broadcast::Sender<Message>,
+    ) -> Result<(), String> {
+        if self.clients_connected.contains_key(&client.name) {
+            return Err(format!("{} is logged in already", client.name));
+        }
+        if let Some(reason) = self.store.name_ban(&client.name) {
+            return Err(format!("{} is banned: {}", client.name, reason));
+        }
+        if self.store.has_password(&client.name) {
+            if !self.store.check_password(&client.name, password) {
+                return Err(format!("Wrong password for {}", client.name));
+            }
+            client.authenticated = true;
+        }
+
+        self.clients_connected_cnt += 1;
+        println!("New client connected: {:?}", client);
+        let _ = client_message_sender.send(Message::UserEnteredChat(UserEnteredChat::new(
+            format!("{} joined the chat!", client.name).into(),
+            client.name.clone().into(),
+        )));
+        let chatter = client.chatter();
+        let mailbox = client.mailbox.clone();
+        let client_name = client.name.clone();
+        self.clients_connected.insert(client.name.clone(), client);
+        // NOTE: never block the server on a single client, the snapshot is small enough to
+        // always fit in a fresh mailbox anyway
+        let read_marker = self.store.read_marker(&client_name).unwrap_or_default();
+        let welcome_back = [
+            Message::WhoIsInChat(self.roster()),
+            Message::ReadMarker(ReadMarker::new(read_marker)),
+            Message::History(self.history_of(&HashSet::new())),
+        ];
+        for message in welcome_back {
+            if let Err(e) = mailbox.try_send(message) {
+                eprintln!("Couldn't catch {} up: {}", client_name, e);
+            }
+        }
+        let _ = client_message_sender.send(Message::RosterAdd(RosterAdd::new(chatter)));
+        self.deliver_offline_messages(&client_name, mailbox);
+        println!(
+            "Current clients connected: {:?}",
+            self.clients_connected.keys()
         );
+        Ok(())
+    }
+
+    /// The role the client gets, which takes having logged in with the name's password
+    fn role_of(&self, name: &str) -> Role {
+        match self.clients_connected.get(name) {
+            Some(client) if client.authenticated => self.store.role(name),
+            _ => Role::User,
+        }
+    }
+
+    /// Writes the store out, it's retried on the next flush if that fails
+    async fn save_store(&mut self) {
+        match self.store.save().await {
+            Ok(()) => self.store_dirty = false,
+            Err(e) => {
+                eprintln!("Failed saving the store: {}", e);
+                self.store_dirty = true;
+            }
+        }
+    }
+
+    /// Sends a message to a single connected client, returns whether it was queued
+    fn send_to(&self, name: &str, message: Message) -> bool {
+        let Some(client) = self.clients_connected.get(name) else {
+            return false;
+        };
+        // NOTE: never block the server on a single slow client
+        if let Err(e) = client.mailbox.try_send(message) {
+            eprintln!("Couldn't deliver a message to {}: {}", name, e);
+            return false;
+        }
+        true
+    }
+
+    fn route_direct_message(&mut self, message: DirectMessage) {
+        let from = String::from_utf8_lossy(&message.from).to_string();
+        let to = String::from_utf8_lossy(&message.to).to_string();
+
+        if self.clients_connected.contains_key(&to) {
+            self.send_to(&to, Message::DirectMessage(message.clone()));
+        } else if self.store.is_registered(&to) {
+            if let Err(e) = self.store.queue_direct_message(&to, message.clone()) {
+                self.send_to(&from, Message::Notice(Notice::error(e.to_string().into())));
+                return;
+            }
+            self.store_dirty = true;
+            self.send_to(
+                &from,
+                Message::Notice(Notice::info(
+                    format!("{to} is offline, they will get your message when they log in").into(),
+                )),
+            );
+        } else {
+            self.send_to(
+                &from,
+                Message::Notice(Notice::error(
+                    format!("Nobody called {to} has ever been here").into(),
+                )),
+            );
+            return;
+        }
+        // NOTE: echo the message back, so that the sender sees what was actually delivered
+        if from != to {
+            self.send_to(&from, Message::DirectMessage(message));
+        }
+    }
+
+    /// Hands over direct messages that were sent while the client was away
+    fn deliver_offline_messages(&mut self, name: &str, mailbox: mpsc::Sender<Message>) {
+        let registered = self.store.register_user(name);
+        let waiting = self.store.take_direct_messages(name);
+        self.store_dirty |= registered || !waiting.is_empty();
+        if waiting.is_empty() {
+            return;
+        }
+
+        let notice = Message::Notice(Notice::info(
+            format!("{} direct messages were waiting for you", waiting.len()).into(),
+        ));
+        // NOTE: there can be more waiting messages than the mailbox holds, so they are fed in
+        // the background rather than blocking the server
+        tokio::spawn(async move {
+            let messages =
+                std::iter::once(notice).chain(waiting.into_iter().map(Message::DirectMessage));
+            for message in messages {
+                if mailbox.send(message).await.is_err() {
+                    eprintln!("Client left before getting all of its offline messages");
+                    break;
+                }
+            }
+        });
+    }
+
+    async fn run_command(&mut self, caller: &str, name: &str, args: &str) {
+        let Some(command) = self.commands.get(name) else {
+            self.send_to(
+                caller,
+                Message::Notice(Notice::error(
+                    format!("Unknown command /{name}, try /commands").into(),
+                )),
+            );
+            return;
+        };
+        let caller_role = self.role_of(caller);
+        if command.required_role() > caller_role {
+            self.send_to(
+                caller,
+                Message::Notice(Notice::error(
+                    format!(
+                        "Permission denied, /{name} is for {}s only",
+                        command.required_role()
+                    )
+                    .into(),
+                )),
+            );
+            return;
+        }
+        let server = ServerInfo {
+            chatters: self.roster().chatters,
+            addresses: self
+                .clients_connected
+                .values()
+                .map(|c| (c.name.clone(), c.address))
+                .collect(),
+            uptime: self.started_at.elapsed(),
+            messages_relayed: self.messages_relayed,
+            store: &self.store,
+            commands: &self.commands,
+        };
+        let caller_authenticated = self
+            .clients_connected
+            .get(caller)
+            .is_some_and(|c| c.authenticated);
+        let mut context =
+            CommandContext::new(caller, caller_role, caller_authenticated, args, server);
+        let notice = match command.run(&mut context) {
+            Ok(reply) => Notice::info(reply.into()),
+            Err(e) => Notice::error(e.to_string().into()),
+        };
+        let effects = context.into_effects();
+        self.send_to(caller, Message::Notice(notice));
+
+        if effects.is_empty() {
+            return;
+        }
+        for effect in effects {
+            self.apply_effect(effect);
+        }
+        self.save_store().await;
+    }
+
+    fn apply_effect(&mut self, effect: Effect) {
+        match effect {
+            Effect::Kick { name, reason } => self.kick(&name, &reason),
+            Effect::Mute(name) => {
+                self.store.set_muted(&name, true);
+                // NOTE: Safety: the lock is never held across an await or a panic
+                self.muted.write().unwrap().insert(name.clone());
+                self.send_to(
+                    &name,
+                    Message::Notice(Notice::error("You have been muted".into())),
+                );
+            }
+            Effect::Unmute(name) => {
+                self.store.set_muted(&name, false);
+                self.muted.write().unwrap().remove(&name);
+                self.send_to(
+                    &name,
+                    Message::Notice(Notice::info("You can talk again".into())),
+                );
+            }
+            Effect::Ban { target, reason } => {
+                let banned: Vec<String> = match target {
+                    BanTarget::Name(name) => {
+                        self.store.ban_name(&name, &reason);
+                        vec![name]
+                    }
+                    BanTarget::Ip(ip) => {
+                        self.store.ban_ip(ip, &reason);
+                        self.clients_connected
+                            .values()
+                            .filter(|c| c.address == ip)
+                            .map(|c| c.name.clone())
+                            .collect()
+                    }
+                };
+                for name in banned {
+                    self.kick(&name, &format!("banned: {reason}"));
+                }
+            }
+            Effect::Unban(target) => {
+                let unbanned = match &target {
+                    BanTarget::Name(name) => self.store.unban_name(name),
+                    BanTarget::Ip(ip) => self.store.unban_ip(ip),
+                };
+                // NOTE: the command checks for the ban, and nothing else runs in between
+                if !unbanned {
+                    eprintln!("Unbanning something that wasn't banned");
+                }
+            }
+            Effect::SetRole { name, role } => {
+                self.store.set_role(&name, role);
+                self.send_to(
+                    &name,
+                    Message::Notice(Notice::info(format!("You are now a {role}").into())),
+                );
+            }
+            Effect::SetPassword { name, password } => {
+                self.store.set_password(&name, password.as_bytes());
+                // NOTE: knowing the new password is as good as logging in with it
+                if let Some(client) = self.clients_connected.get_mut(&name) {
+                    client.authenticated = true;
+                }
+            }
+        }
+    }
+
+    /// Has the client's handler say goodbye and close the connection
+    fn kick(&self, name: &str, reason: &str) {
+        let reason = match reason {
+            "" => "You have been kicked".to_string(),
+            reason => format!("You have been kicked: {reason}"),
+        };
+        // NOTE: a full mailbox would drop the Disconnect, while the moderator is told it worked
+        if let Some(client) = self.clients_connected.get(name) {
+            client.kick.send_replace(Some(reason));
+        }
+    }
+
+    /// The recent messages of the main chat and the given rooms
+    fn history_of(&self, rooms: &HashSet<Bytes>) -> History {
+        History::new(
+            self.history
+                .iter()
+                .filter(|m| m.room.is_empty() || rooms.contains(&m.room))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    fn roster(&self) -> WhoIsInChat {
+        WhoIsInChat::new(
+            self.clients_connected
+                .values()
+                .map(Client::chatter)
+                .collect(),
+        )
+    }
+
+    /// Records the reaction and returns the new aggregated counts of the message, if they changed
+    fn apply_reaction(&mut self, reaction: Reaction) -> Option<ReactionsUpdated> {
+        if reaction.message_id == 0
+            || reaction.message_id >= self.next_message_id.load(Ordering::Relaxed)
+        {
+            eprintln!("Reaction to an unknown message {}", reaction.message_id);
+            return None;
+        }
+
+        let message_reactions = self.reactions.entry(reaction.message_id).or_default();
+        let changed = match reaction.kind {
+            ReactionKind::Add => message_reactions
+                .entry(reaction.emoji)
+                .or_default()
+                .insert(reaction.name),
+            ReactionKind::Remove => {
+                let removed = message_reactions
+                    .get_mut(&reaction.emoji)
+                    .is_some_and(|names| names.remove(&reaction.name));
+                message_reactions.retain(|_, names| !names.is_empty());
+                removed
+            }
+        };
+        if !changed {
+            return None;
+        }
+
+        let counts = message_reactions
+            .iter()
+            .map(|(emoji, names)| (emoji.clone(), names.len() as u64))
+            .collect();
+        if message_reactions.is_empty() {
+            self.reactions.remove(&reaction.message_id);
+        }
+        Some(ReactionsUpdated::new(reaction.message_id, counts))
+    }
+
+    fn spawn_handler_thread(socket: TcpStream, address: IpAddr, context: HandlerContext) {
+        let (read_half, write_half) = socket.into_split();
+        let mut handler =
+            ConnectionHandler::new(Connection::new(read_half, write_half), address, context);
 
         tokio::spawn(async move {
             if let Err(e) = handler.handle().await {
                 eprintln!("An error occured: {}", e);
+                // NOTE: the server never heard of a client that failed before logging in
+                let Some(client) = handler.client.take() else {
+                    return;
+                };
                 let _ = handler
+                    .context
                     .client_status_sender
-                    .send(handler.client.take().unwrap().mark_offline())
+                    .send(client.mark_offline())
                     .await
-                    .map_err(|e| {
+                    .inspect_err(|_| {
                         eprintln!("Couldn't let the server know a client got disconnected");
-                        e
                     });
             }
         });
     }
 }
 
-pub async fn run(listener: TcpListener, shutdown_sig: impl Future) -> Result<()> {
+pub async fn run(
+    listener: TcpListener,
+    shutdown_sig: impl Future,
+    store: Store,
+    commands: CommandRegistry,
+    rate_limit: RateLimit,
+    lag_policy: LagPolicy,
+) -> Result<()> {
     let (notify_shutdown, _) = broadcast::channel(1);
     // TODO: explore client status channel capacity
     let (client_status_sender, client_status_reciever) = mpsc::channel(1);
-    let (client_message_sender, _) = broadcast::channel(20);
+    let (client_message_sender, _) = broadcast::channel(BROADCAST_CAPACITY);
+    let (client_event_sender, client_event_receiver) = mpsc::channel(20);
     let (shutdown_complete, mut shutdown_complete_reciever) = mpsc::channel(1);
 
     let mut server = Server {
@@ -315,11 +1248,34 @@ pub async fn run(listener: TcpListener, shutdown_sig: impl Future) -> Result<()>
         shutdown_complete,
         clients_connected_cnt: 0,
         client_status_reciever,
-        clients_connected: HashSet::new(),
+        client_event_receiver,
+        clients_connected: HashMap::new(),
+        // NOTE: 0 is reserved for messages without an id
+        next_message_id: Arc::new(AtomicU64::new(store.highest_message_id() + 1)),
+        reactions: HashMap::new(),
+        history: VecDeque::with_capacity(HISTORY_CAPACITY),
+        messages_relayed: 0,
+        muted: Arc::new(RwLock::new(store.muted().cloned().collect())),
+        store,
+        store_dirty: false,
+        commands,
+        started_at: Instant::now(),
+    };
+    let context = HandlerContext {
+        notify_shutdown,
+        client_status_sender,
+        client_message_sender,
+        client_event_sender,
+        next_message_id: server.next_message_id.clone(),
+        relay_lock: Arc::new(Mutex::new(())),
+        muted: server.muted.clone(),
+        rate_limit,
+        lag_policy,
+        _shutdown_complete: server.shutdown_complete.clone(),
     };
 
     tokio::select! {
-        run_res = server.run(client_status_sender, notify_shutdown, client_message_sender) => {
+        run_res = server.run(context) => {
             if let Err(err) = run_res {
                 eprintln!("Failed accepting connection: {}", err);
             }
@@ -329,16 +1285,82 @@ pub async fn run(listener: TcpListener, shutdown_sig: impl Future) -> Result<()>
         }
     }
 
+    server.save_store().await;
+
     let Server {
+        tcp_listener,
         notify_shutdown,
         shutdown_complete,
+        client_status_reciever,
+        client_event_receiver,
         ..
     } = server;
 
+    // NOTE: stop accepting, and make sure no handler waits on a server loop that is gone
+    drop(tcp_listener);
+    drop(client_status_reciever);
+    drop(client_event_receiver);
+    // NOTE: every handler holds a sender to subscribe with, so the channel doesn't close on
+    // its own and the shutdown has to be announced
+    let _ = notify_shutdown.send(());
     drop(notify_shutdown);
     drop(shutdown_complete);
 
-    let _ = shutdown_complete_reciever.recv().await;
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown_complete_reciever.recv())
+        .await
+        .is_err()
+    {
+        eprintln!("Some clients didn't disconnect in time, shutting down anyway");
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown_disconnects_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let path = std::env::temp_dir().join(format!("chad-shutdown-{}", std::process::id()));
+        let store = Store::open(&path).await.unwrap();
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        let server = tokio::spawn(run(
+            listener,
+            shutdown_receiver,
+            store,
+            CommandRegistry::with_builtins(),
+            RateLimit::default(),
+            LagPolicy::default(),
+        ));
+
+        let (read_half, write_half) = TcpStream::connect(address).await.unwrap().into_split();
+        let mut connection = Connection::new(read_half, write_half);
+        let login = Message::Login(shared::message::Login::new("alice".into(), Bytes::new()));
+        connection.write_frame(login.into_frame()).await.unwrap();
+        let welcome = Message::from_frame(connection.read_frame().await.unwrap()).unwrap();
+        assert!(matches!(welcome, Message::WelcomeMessage(_)));
+
+        let started = Instant::now();
+        shutdown_sender.send(()).unwrap();
+        let disconnect = tokio::time::timeout(SHUTDOWN_TIMEOUT / 5, async {
+            loop {
+                let frame = connection.read_frame().await.unwrap();
+                if let Message::Disconnect(reason) = Message::from_frame(frame).unwrap() {
+                    return reason;
+                }
+            }
+        })
+        .await
+        .expect("The client wasn't told about the shutdown");
+        assert_eq!(
+            disconnect.reason,
+            Bytes::from_static(b"The server is shutting down")
+        );
+        server.await.unwrap().unwrap();
+        assert!(started.elapsed() < SHUTDOWN_TIMEOUT / 5);
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, ErrorKind};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::vec::IntoIter;
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+
+extern crate shared;
+use shared::message::{DirectMessage, Message};
+use shared::parse_async::Frame;
+
+use crate::moderation::Role;
+
+// Direct messages that may wait for a single recipient, and for everyone together
+const MAX_QUEUED_PER_RECIPIENT: usize = 100;
+const MAX_QUEUED: usize = 10_000;
+
+/// Server state that has to survive reconnects and restarts. It is kept in a single file,
+/// encoded with the same frames that go over the wire
+pub struct Store {
+    path: PathBuf,
+    last_message_id: u64,
+    read_markers: HashMap<String, u64>,
+    // Everyone who has ever logged in
+    registered_users: HashSet<String>,
+    // Direct messages waiting for their recipient to log in, by recipient
+    offline_messages: HashMap<String, Vec<DirectMessage>>,
+    // Anyone above a regular user
+    roles: HashMap<String, Role>,
+    // Hashed passwords of the names that have one, only they may hold a role
+    passwords: HashMap<String, String>,
+    muted: HashSet<String>,
+    // Bans along with their reason
+    banned_names: HashMap<String, String>,
+    banned_ips: HashMap<IpAddr, String>,
+}
+
+impl Store {
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let mut store = Self {
+            path: path.into(),
+            last_message_id: 0,
+            read_markers: HashMap::new(),
+            registered_users: HashSet::new(),
+            offline_messages: HashMap::new(),
+            roles: HashMap::new(),
+            passwords: HashMap::new(),
+            muted: HashSet::new(),
+            banned_names: HashMap::new(),
+            banned_ips: HashMap::new(),
+        };
+        let contents = match tokio::fs::read(&store.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(store),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut sections = into_array(Frame::parse(&mut Cursor::new(&contents[..]))?)?;
+        while let Some(section) = sections.next() {
+            let value = sections
+                .next()
+                .ok_or(anyhow!("Store section without a value"))?;
+            match &into_bulk(section)?[..] {
+                b"last_message_id" => store.last_message_id = parse_u64(&into_bulk(value)?)?,
+                b"read_markers" => {
+                    let mut markers = into_array(value)?;
+                    while let (Some(name), Some(id)) = (markers.next(), markers.next()) {
+                        store.read_markers.insert(
+                            String::from_utf8(into_bulk(name)?.to_vec())?,
+                            parse_u64(&into_bulk(id)?)?,
+                        );
+                    }
+                }
+                b"registered_users" => {
+                    for name in into_array(value)? {
+                        store
+                            .registered_users
+                            .insert(String::from_utf8(into_bulk(name)?.to_vec())?);
+                    }
+                }
+                b"offline_messages" => {
+                    let mut queues = into_array(value)?;
+                    while let (Some(name), Some(queue)) = (queues.next(), queues.next()) {
+                        let mut messages = Vec::new();
+                        for frame in into_array(queue)? {
+                            match Message::from_frame(frame)? {
+                                Message::DirectMessage(m) => messages.push(m),
+                                unexpected => {
+                                    bail!("Expected a direct message, got {:?}", unexpected)
+                                }
+                            }
+                        }
+                        store
+                            .offline_messages
+                            .insert(String::from_utf8(into_bulk(name)?.to_vec())?, messages);
+                    }
+                }
+                b"roles" => {
+                    let mut roles = into_array(value)?;
+                    while let (Some(name), Some(role)) = (roles.next(), roles.next()) {
+                        store.roles.insert(
+                            String::from_utf8(into_bulk(name)?.to_vec())?,
+                            Role::parse(std::str::from_utf8(&into_bulk(role)?)?)?,
+                        );
+                    }
+                }
+                b"passwords" => {
+                    let mut passwords = into_array(value)?;
+                    while let (Some(name), Some(hash)) = (passwords.next(), passwords.next()) {
+                        store.passwords.insert(
+                            String::from_utf8(into_bulk(name)?.to_vec())?,
+                            String::from_utf8(into_bulk(hash)?.to_vec())?,
+                        );
+                    }
+                }
+                b"muted" => {
+                    for name in into_array(value)? {
+                        store
+                            .muted
+                            .insert(String::from_utf8(into_bulk(name)?.to_vec())?);
+                    }
+                }
+                b"banned_names" => {
+                    let mut bans = into_array(value)?;
+                    while let (Some(name), Some(reason)) = (bans.next(), bans.next()) {
+                        store.banned_names.insert(
+                            String::from_utf8(into_bulk(name)?.to_vec())?,
+                            String::from_utf8(into_bulk(reason)?.to_vec())?,
+                        );
+                    }
+                }
+                b"banned_ips" => {
+                    let mut bans = into_array(value)?;
+                    while let (Some(ip), Some(reason)) = (bans.next(), bans.next()) {
+                        store.banned_ips.insert(
+                            std::str::from_utf8(&into_bulk(ip)?)?.parse()?,
+                            String::from_utf8(into_bulk(reason)?.to_vec())?,
+                        );
+                    }
+                }
+                unknown => eprintln!("Skipping unknown store section {:?}", unknown),
+            }
+        }
+        Ok(store)
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        let mut read_markers = Frame::array();
+        self.read_markers.iter().for_each(|(name, id)| {
+            read_markers.push_bulk(Frame::Bulk(name.clone().into()));
+            read_markers.push_bulk(Frame::Bulk(id.to_string().into()));
+        });
+
+        let mut registered_users = Frame::array();
+        self.registered_users.iter().for_each(|name| {
+            registered_users.push_bulk(Frame::Bulk(name.clone().into()));
+        });
+
+        let mut offline_messages = Frame::array();
+        self.offline_messages.iter().for_each(|(name, messages)| {
+            let mut queue = Frame::array();
+            messages.iter().for_each(|m| {
+                queue.push_bulk(Message::DirectMessage(m.clone()).into_frame());
+            });
+            offline_messages.push_bulk(Frame::Bulk(name.clone().into()));
+            offline_messages.push_bulk(queue);
+        });
+
+        let mut roles = Frame::array();
+        self.roles.iter().for_each(|(name, role)| {
+            roles.push_bulk(Frame::Bulk(name.clone().into()));
+            roles.push_bulk(Frame::Bulk(Bytes::from_static(role.as_str().as_bytes())));
+        });
+
+        let mut passwords = Frame::array();
+        self.passwords.iter().for_each(|(name, hash)| {
+            passwords.push_bulk(Frame::Bulk(name.clone().into()));
+            passwords.push_bulk(Frame::Bulk(hash.clone().into()));
+        });
+
+        let mut muted = Frame::array();
+        self.muted.iter().for_each(|name| {
+            muted.push_bulk(Frame::Bulk(name.clone().into()));
+        });
+
+        let mut banned_names = Frame::array();
+        self.banned_names.iter().for_each(|(name, reason)| {
+            banned_names.push_bulk(Frame::Bulk(name.clone().into()));
+            banned_names.push_bulk(Frame::Bulk(reason.clone().into()));
+        });
+
+        let mut banned_ips = Frame::array();
+        self.banned_ips.iter().for_each(|(ip, reason)| {
+            banned_ips.push_bulk(Frame::Bulk(ip.to_string().into()));
+            banned_ips.push_bulk(Frame::Bulk(reason.clone().into()));
+        });
+
+        let mut sections = Frame::array();
+        sections.push_bulk(Frame::Bulk(Bytes::from_static(b"last_message_id")));
+        sections.push_bulk(Frame::Bulk(self.last_message_id.to_string().into()));
+        sections.push_bulk(Frame::Bulk(Bytes::from_static(b"read_markers")));
+        sections.push_bulk(read_markers);
+        sections.push_bulk(Frame::Bulk(Bytes::from_static(b"registered_users")));
+        sections.push_bulk(registered_users);
+        sections.push_bulk(Frame::Bulk(Bytes::from_static(b"offline_messages")));
+        sections.push_bulk(offline_messages);
+        sections.push_bulk(Frame::Bulk(Bytes::from_static(b"roles")));
+        sections.push_bulk(roles);
+        sections.push_bulk(Frame::Bulk(Bytes::from_static(b"passwords")));
+        sections.push_bulk(passwords);
+        sections.push_bulk(Frame::Bulk(Bytes::from_static(b"muted")));
+        sections.push_bulk(muted);
+        sections.push_bulk(Frame::Bulk(Bytes::from_static(b"banned_names")));
+        sections.push_bulk(banned_names);
+        sections.push_bulk(Frame::Bulk(Bytes::from_static(b"banned_ips")));
+        sections.push_bulk(banned_ips);
+
+        let mut encoded = Vec::new();
+        sections.encode(&mut encoded);
+        // NOTE: write aside and swap, so that a crash mid-write doesn't lose everything
+        let tmp_path = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, encoded).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+
+    /// The highest message id known to the store, new ids must be issued above it
+    pub fn highest_message_id(&self) -> u64 {
+        self.read_markers
+            .values()
+            .copied()
+            .fold(self.last_message_id, u64::max)
+    }
+
+    pub fn set_last_message_id(&mut self, id: u64) {
+        self.last_message_id = self.last_message_id.max(id);
+    }
+
+    pub fn read_marker(&self, name: &str) -> Option<u64> {
+        self.read_markers.get(name).copied()
+    }
+
+    /// Moves the marker forward, returns whether it changed
+    pub fn set_read_marker(&mut self, name: &str, id: u64) -> bool {
+        let marker = self.read_markers.entry(name.to_string()).or_default();
+        if id <= *marker {
+            return false;
+        }
+        *marker = id;
+        true
+    }
+
+    /// Remembers the user, returns whether they are new
+    pub fn register_user(&mut self, name: &str) -> bool {
+        self.registered_users.insert(name.to_string())
+    }
+
+    pub fn registered_users(&self) -> usize {
+        self.registered_users.len()
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.registered_users.contains(name)
+    }
+
+    /// Keeps the message until the recipient logs in, unless too many are waiting already
+    pub fn queue_direct_message(&mut self, to: &str, message: DirectMessage) -> Result<()> {
+        let queued: usize = self.offline_messages.values().map(Vec::len).sum();
+        if queued >= MAX_QUEUED {
+            bail!("Too many messages are waiting to be delivered, try again later");
+        }
+        let queue = self.offline_messages.entry(to.to_string()).or_default();
+        if queue.len() >= MAX_QUEUED_PER_RECIPIENT {
+            bail!("{} has too many messages waiting already", to);
+        }
+        queue.push(message);
+        Ok(())
+    }
+
+    pub fn take_direct_messages(&mut self, name: &str) -> Vec<DirectMessage> {
+        self.offline_messages.remove(name).unwrap_or_default()
+    }
+
+    pub fn role(&self, name: &str) -> Role {
+        self.roles.get(name).copied().unwrap_or_default()
+    }
+
+    pub fn set_role(&mut self, name: &str, role: Role) {
+        match role {
+            Role::User => self.roles.remove(name),
+            role => self.roles.insert(name.to_string(), role),
+        };
+    }
+
+    pub fn has_password(&self, name: &str) -> bool {
+        self.passwords.contains_key(name)
+    }
+
+    pub fn check_password(&self, name: &str, password: &[u8]) -> bool {
+        self.passwords
+            .get(name)
+            .is_some_and(|hash| *hash == hash_password(name, password))
+    }
+
+    pub fn set_password(&mut self, name: &str, password: &[u8]) {
+        self.passwords
+            .insert(name.to_string(), hash_password(name, password));
+    }
+
+    pub fn muted(&self) -> impl Iterator<Item = &String> {
+        self.muted.iter()
+    }
+
+    pub fn is_muted(&self, name: &str) -> bool {
+        self.muted.contains(name)
+    }
+
+    pub fn set_muted(&mut self, name: &str, muted: bool) {
+        if muted {
+            self.muted.insert(name.to_string());
+        } else {
+            self.muted.remove(name);
+        }
+    }
+
+    /// The ban reason, if the name is banned
+    pub fn name_ban(&self, name: &str) -> Option<&str> {
+        self.banned_names.get(name).map(String::as_str)
+    }
+
+    pub fn ip_ban(&self, ip: &IpAddr) -> Option<&str> {
+        self.banned_ips.get(ip).map(String::as_str)
+    }
+
+    pub fn ban_name(&mut self, name: &str, reason: &str) {
+        self.banned_names
+            .insert(name.to_string(), reason.to_string());
+    }
+
+    pub fn ban_ip(&mut self, ip: IpAddr, reason: &str) {
+        self.banned_ips.insert(ip, reason.to_string());
+    }
+
+    /// Returns whether there was a ban to lift
+    pub fn unban_name(&mut self, name: &str) -> bool {
+        self.banned_names.remove(name).is_some()
+    }
+
+    pub fn unban_ip(&mut self, ip: &IpAddr) -> bool {
+        self.banned_ips.remove(ip).is_some()
+    }
+}
+
+/// Hex encoded sha256 of the password, salted with the name so that equal passwords differ
+fn hash_password(name: &str, password: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(name.as_bytes());
+    hasher.update(b":");
+    hasher.update(password);
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn into_array(frame: Frame) -> Result<IntoIter<Frame>> {
+    match frame {
+        Frame::Array(a) => Ok(a.into_iter()),
+        Frame::Bulk(_) => bail!("Corrupted store, expected an array"),
+    }
+}
+
+fn into_bulk(frame: Frame) -> Result<Bytes> {
+    match frame {
+        Frame::Bulk(b) => Ok(b),
+        Frame::Array(_) => bail!("Corrupted store, expected a bulk string"),
+    }
+}
+
+fn parse_u64(bytes: &[u8]) -> Result<u64> {
+    Ok(std::str::from_utf8(bytes)?.parse::<u64>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_store_round_trip() {
+        let path = std::env::temp_dir().join(format!("chad-store-{}", std::process::id()));
+        let mut store = Store::open(&path)
+            .await
+            .expect("Failed opening an empty store");
+        assert_eq!(store.highest_message_id(), 0);
+
+        store.set_last_message_id(10);
+        assert!(store.set_read_marker("alice", 7));
+        assert!(!store.set_read_marker("alice", 5));
+        assert!(store.register_user("bob"));
+        store
+            .queue_direct_message(
+                "bob",
+                DirectMessage::new(
+                    Bytes::from_static(b"alice"),
+                    Bytes::from_static(b"bob"),
+                    chrono::Local::now(),
+                    Bytes::from_static(b"call me\r\nmaybe"),
+                ),
+            )
+            .unwrap();
+        store.set_role("alice", Role::Moderator);
+        store.set_password("alice", b"hunter2");
+        store.set_muted("bob", true);
+        store.ban_ip("10.0.0.1".parse().unwrap(), "spam");
+        store.save().await.expect("Failed saving the store");
+
+        let mut reopened = Store::open(&path)
+            .await
+            .expect("Failed reopening the store");
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert_eq!(reopened.read_marker("alice"), Some(7));
+        assert_eq!(reopened.read_marker("bob"), None);
+        assert_eq!(reopened.highest_message_id(), 10);
+        assert!(reopened.is_registered("bob"));
+        assert!(!reopened.is_registered("carol"));
+        assert_eq!(reopened.role("alice"), Role::Moderator);
+        assert_eq!(reopened.role("bob"), Role::User);
+        assert!(reopened.check_password("alice", b"hunter2"));
+        assert!(!reopened.check_password("alice", b"hunter3"));
+        assert!(!reopened.check_password("bob", b""));
+        assert!(reopened.is_muted("bob"));
+        assert_eq!(reopened.ip_ban(&"10.0.0.1".parse().unwrap()), Some("spam"));
+        assert_eq!(reopened.name_ban("bob"), None);
+
+        let waiting = reopened.take_direct_messages("bob");
+        assert_eq!(waiting.len(), 1);
+        assert_eq!(waiting[0].msg, Bytes::from_static(b"call me\r\nmaybe"));
+        assert!(reopened.take_direct_messages("bob").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_direct_message_limits() {
+        let mut store = Store::open(std::env::temp_dir().join("chad-store-never-saved"))
+            .await
+            .expect("Failed opening an empty store");
+        let message = DirectMessage::new(
+            Bytes::from_static(b"alice"),
+            Bytes::from_static(b"bob"),
+            chrono::Local::now(),
+            Bytes::from_static(b"hi"),
+        );
+        for _ in 0..MAX_QUEUED_PER_RECIPIENT {
+            store.queue_direct_message("bob", message.clone()).unwrap();
+        }
+        assert!(store.queue_direct_message("bob", message.clone()).is_err());
+
+        let recipients = MAX_QUEUED / MAX_QUEUED_PER_RECIPIENT;
+        for i in 1..recipients {
+            for _ in 0..MAX_QUEUED_PER_RECIPIENT {
+                store
+                    .queue_direct_message(&format!("user{i}"), message.clone())
+                    .unwrap();
+            }
+        }
+        assert!(store.queue_direct_message("carol", message).is_err());
+    }
+}
@@ -4,6 +4,8 @@ use crate::parse_async::Frame;
 use anyhow::{anyhow, bail, Result};
 use bytes::Bytes;
 
+const MAX_ROOM_BYTES: usize = 32;
+
 #[derive(Clone, Debug)]
 pub enum Message {
     Login(Login),
@@ -13,6 +15,19 @@ pub enum Message {
     UserEnteredChat(UserEnteredChat),
     UserLeftChat(UserLeftChat),
     WhoIsInChat(WhoIsInChat),
+    Reaction(Reaction),
+    ReactionsUpdated(ReactionsUpdated),
+    Typing(Typing),
+    SetStatus(SetStatus),
+    RosterAdd(RosterAdd),
+    RosterRemove(RosterRemove),
+    ReadMarker(ReadMarker),
+    History(History),
+    DirectMessage(DirectMessage),
+    Notice(Notice),
+    Disconnect(Disconnect),
+    JoinRoom(JoinRoom),
+    PartRoom(PartRoom),
 }
 
 impl Message {
@@ -30,6 +45,19 @@ impl Message {
             b"user_entered_chat" => Ok(Self::UserEnteredChat(UserEnteredChat::parse(parser)?)),
             b"user_left_chat" => Ok(Self::UserLeftChat(UserLeftChat::parse(parser)?)),
             b"who_is_in_chat" => Ok(Self::WhoIsInChat(WhoIsInChat::parse(parser)?)),
+            b"reaction" => Ok(Self::Reaction(Reaction::parse(parser)?)),
+            b"reactions_updated" => Ok(Self::ReactionsUpdated(ReactionsUpdated::parse(parser)?)),
+            b"typing" => Ok(Self::Typing(Typing::parse(parser)?)),
+            b"set_status" => Ok(Self::SetStatus(SetStatus::parse(parser)?)),
+            b"roster_add" => Ok(Self::RosterAdd(RosterAdd::parse(parser)?)),
+            b"roster_remove" => Ok(Self::RosterRemove(RosterRemove::parse(parser)?)),
+            b"read_marker" => Ok(Self::ReadMarker(ReadMarker::parse(parser)?)),
+            b"history" => Ok(Self::History(History::parse(parser)?)),
+            b"direct_message" => Ok(Self::DirectMessage(DirectMessage::parse(parser)?)),
+            b"notice" => Ok(Self::Notice(Notice::parse(parser)?)),
+            b"disconnect" => Ok(Self::Disconnect(Disconnect::parse(parser)?)),
+            b"join_room" => Ok(Self::JoinRoom(JoinRoom::parse(parser)?)),
+            b"part_room" => Ok(Self::PartRoom(PartRoom::parse(parser)?)),
             unknown => bail!("Unknown message kind: {:?}", unknown),
         }
     }
@@ -41,6 +69,7 @@ impl Message {
                 let mut frame = Frame::array();
                 frame.push_bulk(Frame::Bulk(Bytes::from_static(b"login")));
                 frame.push_bulk(Frame::Bulk(msg.name));
+                frame.push_bulk(Frame::Bulk(msg.password));
                 frame
             }
             Self::Logout(msg) => {
@@ -52,9 +81,16 @@ impl Message {
             Self::ChatMessage(msg) => {
                 let mut frame = Frame::array();
                 frame.push_bulk(Frame::Bulk(Bytes::from_static(b"chat_message")));
+                frame.push_bulk(Frame::Bulk(msg.id.to_string().into()));
                 frame.push_bulk(Frame::Bulk(msg.name));
                 frame.push_bulk(Frame::Bulk(msg.msg));
                 frame.push_bulk(Frame::Bulk(msg.sent_at));
+                let mut mentions = Frame::array();
+                msg.mentions
+                    .into_iter()
+                    .for_each(|name| mentions.push_bulk(Frame::Bulk(name)));
+                frame.push_bulk(mentions);
+                frame.push_bulk(Frame::Bulk(msg.room));
                 frame
             }
             Self::WelcomeMessage(msg) => {
@@ -83,30 +119,535 @@ impl Message {
                 frame.push_bulk(Frame::Bulk(Bytes::from_static(b"who_is_in_chat")));
 
                 let mut chatters_array = Frame::array();
-                msg.chatters.iter().for_each(|el| {
-                    chatters_array.push_bulk(Frame::Bulk(el.clone()));
+                msg.chatters.into_iter().for_each(|chatter| {
+                    chatters_array.push_bulk(chatter.into_frame());
                 });
                 frame.push_bulk(chatters_array);
-                println!("View as frame: {:?}", frame);
+                frame
+            }
+            Self::Reaction(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"reaction")));
+                frame.push_bulk(Frame::Bulk(msg.message_id.to_string().into()));
+                frame.push_bulk(Frame::Bulk(msg.name));
+                frame.push_bulk(Frame::Bulk(msg.emoji));
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(msg.kind.as_bytes())));
+                frame
+            }
+            Self::ReactionsUpdated(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"reactions_updated")));
+                frame.push_bulk(Frame::Bulk(msg.message_id.to_string().into()));
+
+                let mut reactions_array = Frame::array();
+                msg.reactions.into_iter().for_each(|(emoji, count)| {
+                    reactions_array.push_bulk(Frame::Bulk(emoji));
+                    reactions_array.push_bulk(Frame::Bulk(count.to_string().into()));
+                });
+                frame.push_bulk(reactions_array);
+                frame
+            }
+            Self::Typing(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"typing")));
+                frame.push_bulk(Frame::Bulk(msg.name));
+                frame
+            }
+            Self::SetStatus(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"set_status")));
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(msg.presence.as_bytes())));
+                frame.push_bulk(Frame::Bulk(msg.status_text));
+                frame
+            }
+            Self::RosterAdd(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"roster_add")));
+                frame.push_bulk(msg.chatter.into_frame());
+                frame
+            }
+            Self::RosterRemove(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"roster_remove")));
+                frame.push_bulk(Frame::Bulk(msg.name));
+                frame
+            }
+            Self::ReadMarker(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"read_marker")));
+                frame.push_bulk(Frame::Bulk(msg.message_id.to_string().into()));
+                frame
+            }
+            Self::History(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"history")));
+
+                let mut messages_array = Frame::array();
+                msg.messages.into_iter().for_each(|m| {
+                    messages_array.push_bulk(Self::ChatMessage(m).into_frame());
+                });
+                frame.push_bulk(messages_array);
+                frame
+            }
+            Self::DirectMessage(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"direct_message")));
+                frame.push_bulk(Frame::Bulk(msg.from));
+                frame.push_bulk(Frame::Bulk(msg.to));
+                frame.push_bulk(Frame::Bulk(msg.msg));
+                frame.push_bulk(Frame::Bulk(msg.sent_at));
+                frame
+            }
+            Self::Notice(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"notice")));
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(msg.level.as_bytes())));
+                frame.push_bulk(Frame::Bulk(msg.msg));
+                frame
+            }
+            Self::Disconnect(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"disconnect")));
+                frame.push_bulk(Frame::Bulk(msg.reason));
+                frame
+            }
+            Self::JoinRoom(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"join_room")));
+                frame.push_bulk(Frame::Bulk(msg.room));
+                frame
+            }
+            Self::PartRoom(msg) => {
+                let mut frame = Frame::array();
+                frame.push_bulk(Frame::Bulk(Bytes::from_static(b"part_room")));
+                frame.push_bulk(Frame::Bulk(msg.room));
                 frame
             }
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReactionKind {
+    Add,
+    Remove,
+}
+
+impl ReactionKind {
+    fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            Self::Add => b"add",
+            Self::Remove => b"remove",
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            b"add" => Ok(Self::Add),
+            b"remove" => Ok(Self::Remove),
+            unknown => bail!("Unknown reaction kind: {:?}", unknown),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
-pub struct WhoIsInChat {
-    pub chatters: Vec<Bytes>,
+pub struct Reaction {
+    pub message_id: u64,
+    pub name: Bytes,
+    pub emoji: Bytes,
+    pub kind: ReactionKind,
 }
 
-impl WhoIsInChat {
+impl Reaction {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        Ok(Self {
+            message_id: parser.next_u64()?,
+            name: parser.next_bytes()?,
+            emoji: parser.next_bytes()?,
+            kind: ReactionKind::parse(&parser.next_bytes()?)?,
+        })
+    }
+
+    pub fn new(message_id: u64, name: Bytes, emoji: Bytes, kind: ReactionKind) -> Self {
+        Self {
+            message_id,
+            name,
+            emoji,
+            kind,
+        }
+    }
+}
+
+/// Aggregated reaction counts of a single message, sent whenever they change
+#[derive(Clone, Debug)]
+pub struct ReactionsUpdated {
+    pub message_id: u64,
+    pub reactions: Vec<(Bytes, u64)>,
+}
+
+impl ReactionsUpdated {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        let message_id = parser.next_u64()?;
+        let flattened = parser.next_array()?;
+        if !flattened.len().is_multiple_of(2) {
+            bail!("Expected emoji and count pairs, got an odd number of elements");
+        }
+
+        let mut reactions = Vec::with_capacity(flattened.len() / 2);
+        for pair in flattened.chunks(2) {
+            let count = std::str::from_utf8(&pair[1])?.parse::<u64>()?;
+            reactions.push((pair[0].clone(), count));
+        }
+
+        Ok(Self {
+            message_id,
+            reactions,
+        })
+    }
+
+    pub fn new(message_id: u64, reactions: Vec<(Bytes, u64)>) -> Self {
+        Self {
+            message_id,
+            reactions,
+        }
+    }
+}
+
+/// Ephemeral "user is typing" notification, relayed to everyone but never stored
+#[derive(Clone, Debug)]
+pub struct Typing {
+    pub name: Bytes,
+}
+
+impl Typing {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        Ok(Self {
+            name: parser.next_bytes()?,
+        })
+    }
+
+    pub fn new(name: Bytes) -> Self {
+        Self { name }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Presence {
+    #[default]
+    Online,
+    Away,
+    Busy,
+    DoNotDisturb,
+}
+
+impl Presence {
+    fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            Self::Online => b"online",
+            Self::Away => b"away",
+            Self::Busy => b"busy",
+            Self::DoNotDisturb => b"dnd",
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            b"online" => Ok(Self::Online),
+            b"away" => Ok(Self::Away),
+            b"busy" => Ok(Self::Busy),
+            b"dnd" => Ok(Self::DoNotDisturb),
+            unknown => bail!("Unknown presence: {:?}", unknown),
+        }
+    }
+}
+
+impl std::fmt::Display for Presence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Online => write!(f, "Online"),
+            Self::Away => write!(f, "Away"),
+            Self::Busy => write!(f, "Busy"),
+            Self::DoNotDisturb => write!(f, "Do not disturb"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SetStatus {
+    pub presence: Presence,
+    pub status_text: Bytes,
+}
+
+impl SetStatus {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        Ok(Self {
+            presence: Presence::parse(&parser.next_bytes()?)?,
+            status_text: parser.next_bytes()?,
+        })
+    }
+
+    pub fn new(presence: Presence, status_text: Bytes) -> Self {
+        Self {
+            presence,
+            status_text,
+        }
+    }
+}
+
+/// A single entry of the online users roster
+#[derive(Clone, Debug)]
+pub struct Chatter {
+    pub name: Bytes,
+    pub presence: Presence,
+    pub status_text: Bytes,
+    // Unix timestamp in milliseconds
+    pub joined_at: u64,
+    pub messages_sent: u64,
+}
+
+impl Chatter {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        Ok(Self {
+            name: parser.next_bytes()?,
+            presence: Presence::parse(&parser.next_bytes()?)?,
+            status_text: parser.next_bytes()?,
+            joined_at: parser.next_u64()?,
+            messages_sent: parser.next_u64()?,
+        })
+    }
+
+    pub fn new(
+        name: Bytes,
+        presence: Presence,
+        status_text: Bytes,
+        joined_at: u64,
+        messages_sent: u64,
+    ) -> Self {
+        Self {
+            name,
+            presence,
+            status_text,
+            joined_at,
+            messages_sent,
+        }
+    }
+
+    fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Frame::Bulk(self.name));
+        frame.push_bulk(Frame::Bulk(Bytes::from_static(self.presence.as_bytes())));
+        frame.push_bulk(Frame::Bulk(self.status_text));
+        frame.push_bulk(Frame::Bulk(self.joined_at.to_string().into()));
+        frame.push_bulk(Frame::Bulk(self.messages_sent.to_string().into()));
+        frame
+    }
+}
+
+/// Roster delta: a chatter joined or their entry changed, replaces any entry with the same name
+#[derive(Clone, Debug)]
+pub struct RosterAdd {
+    pub chatter: Chatter,
+}
+
+impl RosterAdd {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        Ok(Self {
+            chatter: Chatter::parse(parser.next_parser()?)?,
+        })
+    }
+
+    pub fn new(chatter: Chatter) -> Self {
+        Self { chatter }
+    }
+}
+
+/// Roster delta: a chatter left
+#[derive(Clone, Debug)]
+pub struct RosterRemove {
+    pub name: Bytes,
+}
+
+impl RosterRemove {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        Ok(Self {
+            name: parser.next_bytes()?,
+        })
+    }
+
+    pub fn new(name: Bytes) -> Self {
+        Self { name }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DirectMessage {
+    pub from: Bytes,
+    pub to: Bytes,
+    // NOTE: dated, unlike chat messages, since it may be delivered days after being sent
+    pub sent_at: Bytes,
+    pub msg: Bytes,
+}
+
+impl DirectMessage {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        let from = parser.next_bytes()?;
+        let to = parser.next_bytes()?;
+        let msg = parser.next_bytes()?;
+        let sent_at = parser.next_bytes()?;
+
+        Ok(Self {
+            from,
+            to,
+            sent_at,
+            msg,
+        })
+    }
+
+    pub fn new(
+        from: Bytes,
+        to: Bytes,
+        sent_at: chrono::DateTime<chrono::Local>,
+        msg: Bytes,
+    ) -> Self {
+        let sent_at_fmt = sent_at.format("%Y-%m-%d %H:%M:%S").to_string();
+        Self {
+            from,
+            to,
+            sent_at: sent_at_fmt.into(),
+            msg,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoticeLevel {
+    Info,
+    Error,
+}
+
+impl NoticeLevel {
+    fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            Self::Info => b"info",
+            Self::Error => b"error",
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            b"info" => Ok(Self::Info),
+            b"error" => Ok(Self::Error),
+            unknown => bail!("Unknown notice level: {:?}", unknown),
+        }
+    }
+}
+
+/// A server reply meant for a single client only
+#[derive(Clone, Debug)]
+pub struct Notice {
+    pub level: NoticeLevel,
+    pub msg: Bytes,
+}
+
+impl Notice {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        Ok(Self {
+            level: NoticeLevel::parse(&parser.next_bytes()?)?,
+            msg: parser.next_bytes()?,
+        })
+    }
+
+    pub fn info(msg: Bytes) -> Self {
+        Self {
+            level: NoticeLevel::Info,
+            msg,
+        }
+    }
+
+    pub fn error(msg: Bytes) -> Self {
+        Self {
+            level: NoticeLevel::Error,
+            msg,
+        }
+    }
+}
+
+/// The server is about to close the connection, and tells the client why
+#[derive(Clone, Debug)]
+pub struct Disconnect {
+    pub reason: Bytes,
+}
+
+impl Disconnect {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        Ok(Self::new(parser.next_bytes()?))
+    }
+
+    pub fn new(reason: Bytes) -> Self {
+        Self { reason }
+    }
+}
+
+/// The id of the last message a user has seen. Clients report it as they read, the server
+/// sends the stored one back on login
+#[derive(Clone, Debug)]
+pub struct ReadMarker {
+    pub message_id: u64,
+}
+
+impl ReadMarker {
     fn parse(mut parser: Parser) -> Result<Self> {
         Ok(Self {
-            chatters: parser.next_array()?,
+            message_id: parser.next_u64()?,
         })
     }
 
-    pub fn new(chatters: Vec<Bytes>) -> Self {
+    pub fn new(message_id: u64) -> Self {
+        Self { message_id }
+    }
+}
+
+/// Recent chat messages replayed to a client right after it logs in, oldest first
+#[derive(Clone, Debug)]
+pub struct History {
+    pub messages: Vec<ChatMessage>,
+}
+
+impl History {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        let mut messages_parser = parser.next_parser()?;
+        let mut messages = Vec::new();
+        while messages_parser.has_remaining() {
+            match Message::from_frame(messages_parser.next()?)? {
+                Message::ChatMessage(m) => messages.push(m),
+                unexpected => bail!("Expected a chat message in history, got {:?}", unexpected),
+            }
+        }
+        Ok(Self { messages })
+    }
+
+    pub fn new(messages: Vec<ChatMessage>) -> Self {
+        Self { messages }
+    }
+}
+
+/// Full roster snapshot, only sent to a client right after it logs in
+#[derive(Clone, Debug)]
+pub struct WhoIsInChat {
+    pub chatters: Vec<Chatter>,
+}
+
+impl WhoIsInChat {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        let mut chatters_parser = parser.next_parser()?;
+        let mut chatters = Vec::new();
+        while chatters_parser.has_remaining() {
+            chatters.push(Chatter::parse(chatters_parser.next_parser()?)?);
+        }
+        Ok(Self { chatters })
+    }
+
+    pub fn new(chatters: Vec<Chatter>) -> Self {
         Self { chatters }
     }
 }
@@ -180,17 +721,59 @@ impl WelcomeMessage {
 #[derive(Clone, Debug)]
 pub struct Login {
     pub name: Bytes,
+    // Empty for names that don't have a password
+    pub password: Bytes,
 }
 
 impl Login {
     fn parse(mut parser: Parser) -> Result<Self> {
         Ok(Self {
             name: parser.next_bytes()?,
+            password: parser.next_bytes()?,
         })
     }
 
-    pub fn new(name: Bytes) -> Self {
-        Self { name }
+    pub fn new(name: Bytes, password: Bytes) -> Self {
+        Self { name, password }
+    }
+}
+
+/// Room names start with a '#' and can't have whitespace in them, e.g. "#rust"
+pub fn is_room_name(room: &str) -> bool {
+    room.len() > 1
+        && room.len() <= MAX_ROOM_BYTES
+        && room.starts_with('#')
+        && !room.contains(char::is_whitespace)
+}
+
+/// Asks the server for the messages of a room, on top of the main chat
+#[derive(Clone, Debug)]
+pub struct JoinRoom {
+    pub room: Bytes,
+}
+
+impl JoinRoom {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        Ok(Self::new(parser.next_bytes()?))
+    }
+
+    pub fn new(room: Bytes) -> Self {
+        Self { room }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PartRoom {
+    pub room: Bytes,
+}
+
+impl PartRoom {
+    fn parse(mut parser: Parser) -> Result<Self> {
+        Ok(Self::new(parser.next_bytes()?))
+    }
+
+    pub fn new(room: Bytes) -> Self {
+        Self { room }
     }
 }
 
@@ -206,33 +789,81 @@ impl Logout {
         Ok(Self::new(parser.next_bytes()?))
     }
 
-    fn new(name: Bytes) -> Self {
+    pub fn new(name: Bytes) -> Self {
         Self { name }
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct ChatMessage {
+    // NOTE: 0 means the id is not assigned yet, the server stamps every message it relays
+    pub id: u64,
     pub name: Bytes,
     pub sent_at: Bytes,
     pub msg: Bytes,
+    // Names @mentioned in the message, tagged by the server
+    pub mentions: Vec<Bytes>,
+    // Empty for the main chat that everyone is in
+    pub room: Bytes,
 }
 
 impl ChatMessage {
     fn parse(mut parser: Parser) -> Result<Self> {
+        let id = parser.next_u64()?;
         let name = parser.next_bytes()?;
         let msg = parser.next_bytes()?;
         let sent_at = parser.next_bytes()?;
+        let mentions = parser.next_array()?;
+        let room = parser.next_bytes()?;
+
+        Ok(Self {
+            id,
+            name,
+            sent_at,
+            msg,
+            mentions,
+            room,
+        })
+    }
 
-        Ok(Self { name, sent_at, msg })
+    /// Fills `mentions` with every distinct "@name" in the message
+    pub fn tag_mentions(&mut self) {
+        let msg = String::from_utf8_lossy(&self.msg);
+        let mut mentions: Vec<Bytes> = Vec::new();
+        for (start, _) in msg.match_indices('@') {
+            let name: String = msg[start + 1..]
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
+                .collect();
+            // NOTE: "mail@example" is an address, not a mention
+            let after_word = msg[..start]
+                .chars()
+                .next_back()
+                .is_some_and(char::is_alphanumeric);
+            if name.is_empty() || after_word {
+                continue;
+            }
+            let name = Bytes::from(name);
+            if !mentions.contains(&name) {
+                mentions.push(name);
+            }
+        }
+        self.mentions = mentions;
+    }
+
+    pub fn mentions(&self, name: &str) -> bool {
+        self.mentions.iter().any(|m| m == name.as_bytes())
     }
 
     pub fn new(name: Bytes, sent_at: chrono::DateTime<chrono::Local>, msg: Bytes) -> Self {
         let sent_at_fmt = sent_at.time().format("%H:%M:%S").to_string();
         Self {
+            id: 0,
             name,
             sent_at: sent_at_fmt.into(),
             msg,
+            mentions: Vec::new(),
+            room: Bytes::new(),
         }
     }
 }
@@ -262,6 +893,18 @@ impl Parser {
         }
     }
 
+    fn has_remaining(&self) -> bool {
+        self.frame.len() > 0
+    }
+
+    /// Parser over the nested array coming next
+    fn next_parser(&mut self) -> Result<Self> {
+        match self.next()? {
+            Frame::Array(a) => Self::new(Frame::Array(a)),
+            Frame::Bulk(_) => bail!("Expected array, found bulk string"),
+        }
+    }
+
     fn next_array(&mut self) -> Result<Vec<Bytes>> {
         if let Frame::Array(a) = self.next()? {
             let mut array: Vec<Bytes> = Vec::new();
@@ -276,6 +919,7 @@ impl Parser {
         bail!("Expected array, got something else")
     }
 
+    #[allow(dead_code)]
     fn next_i64(&mut self) -> Result<i64> {
         let bytes = self.next_bytes()?;
         let str_num = std::str::from_utf8(&bytes)?;
@@ -283,4 +927,219 @@ impl Parser {
 
         Ok(parsed_i64)
     }
+
+    fn next_u64(&mut self) -> Result<u64> {
+        let bytes = self.next_bytes()?;
+        let str_num = std::str::from_utf8(&bytes)?;
+        let parsed_u64 = str_num.parse::<u64>()?;
+
+        Ok(parsed_u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: Message) -> Message {
+        Message::from_frame(message.into_frame()).expect("Failed parsing own frame")
+    }
+
+    #[test]
+    fn test_chat_message_keeps_id() {
+        let mut msg = ChatMessage::new(
+            Bytes::from_static(b"alice"),
+            chrono::Local::now(),
+            Bytes::from_static(b"hi"),
+        );
+        msg.id = 42;
+
+        match round_trip(Message::ChatMessage(msg)) {
+            Message::ChatMessage(m) => {
+                assert_eq!(m.id, 42);
+                assert_eq!(m.name, Bytes::from_static(b"alice"));
+                assert_eq!(m.msg, Bytes::from_static(b"hi"));
+            }
+            other => panic!("Expected a chat message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_chat_message_mentions() {
+        let mut msg = ChatMessage::new(
+            Bytes::from_static(b"alice"),
+            chrono::Local::now(),
+            Bytes::from_static(b"@bob, ask @carol_2 or @bob, not mail@example.com or @"),
+        );
+        msg.tag_mentions();
+
+        match round_trip(Message::ChatMessage(msg)) {
+            Message::ChatMessage(m) => {
+                assert_eq!(
+                    m.mentions,
+                    vec![Bytes::from_static(b"bob"), Bytes::from_static(b"carol_2")]
+                );
+                assert!(m.mentions("bob"));
+                assert!(!m.mentions("example"));
+            }
+            other => panic!("Expected a chat message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_chat_message_in_room() {
+        let mut msg = ChatMessage::new(
+            Bytes::from_static(b"alice"),
+            chrono::Local::now(),
+            Bytes::from_static(b"hi"),
+        );
+        msg.room = Bytes::from_static(b"#rust");
+
+        match round_trip(Message::ChatMessage(msg)) {
+            Message::ChatMessage(m) => assert_eq!(m.room, Bytes::from_static(b"#rust")),
+            other => panic!("Expected a chat message, got {:?}", other),
+        }
+        match round_trip(Message::JoinRoom(JoinRoom::new(Bytes::from_static(
+            b"#rust",
+        )))) {
+            Message::JoinRoom(m) => assert_eq!(m.room, Bytes::from_static(b"#rust")),
+            other => panic!("Expected joining a room, got {:?}", other),
+        }
+
+        assert!(is_room_name("#rust"));
+        assert!(!is_room_name("rust"));
+        assert!(!is_room_name("#"));
+        assert!(!is_room_name("#two words"));
+    }
+
+    #[test]
+    fn test_who_is_in_chat_round_trip() {
+        let roster = WhoIsInChat::new(vec![
+            Chatter::new(
+                Bytes::from_static(b"alice"),
+                Presence::Busy,
+                Bytes::from_static(b"in a meeting"),
+                1_718_000_000_000,
+                12,
+            ),
+            Chatter::new(
+                Bytes::from_static(b"bob"),
+                Presence::Online,
+                Bytes::new(),
+                1_718_000_500_000,
+                0,
+            ),
+        ]);
+
+        match round_trip(Message::WhoIsInChat(roster)) {
+            Message::WhoIsInChat(r) => {
+                assert_eq!(r.chatters.len(), 2);
+                assert_eq!(r.chatters[0].name, Bytes::from_static(b"alice"));
+                assert_eq!(r.chatters[0].presence, Presence::Busy);
+                assert_eq!(
+                    r.chatters[0].status_text,
+                    Bytes::from_static(b"in a meeting")
+                );
+                assert_eq!(r.chatters[1].presence, Presence::Online);
+                assert!(r.chatters[1].status_text.is_empty());
+            }
+            other => panic!("Expected a roster, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_roster_add_round_trip() {
+        let chatter = Chatter::new(
+            Bytes::from_static(b"carol"),
+            Presence::Away,
+            Bytes::new(),
+            1_718_000_000_000,
+            3,
+        );
+
+        match round_trip(Message::RosterAdd(RosterAdd::new(chatter))) {
+            Message::RosterAdd(r) => {
+                assert_eq!(r.chatter.name, Bytes::from_static(b"carol"));
+                assert_eq!(r.chatter.presence, Presence::Away);
+                assert_eq!(r.chatter.messages_sent, 3);
+            }
+            other => panic!("Expected a roster delta, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_history_round_trip() {
+        let mut first = ChatMessage::new(
+            Bytes::from_static(b"alice"),
+            chrono::Local::now(),
+            Bytes::from_static(b"first"),
+        );
+        first.id = 1;
+        let mut second = first.clone();
+        second.id = 2;
+        second.msg = Bytes::from_static(b"second");
+
+        match round_trip(Message::History(History::new(vec![first, second]))) {
+            Message::History(h) => {
+                assert_eq!(h.messages.len(), 2);
+                assert_eq!(h.messages[0].id, 1);
+                assert_eq!(h.messages[1].msg, Bytes::from_static(b"second"));
+            }
+            other => panic!("Expected history, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_direct_message_round_trip() {
+        let dm = DirectMessage::new(
+            Bytes::from_static(b"alice"),
+            Bytes::from_static(b"bob"),
+            chrono::Local::now(),
+            Bytes::from_static(b"psst"),
+        );
+
+        match round_trip(Message::DirectMessage(dm)) {
+            Message::DirectMessage(m) => {
+                assert_eq!(m.from, Bytes::from_static(b"alice"));
+                assert_eq!(m.to, Bytes::from_static(b"bob"));
+                assert_eq!(m.msg, Bytes::from_static(b"psst"));
+            }
+            other => panic!("Expected a direct message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reaction_round_trip() {
+        let reaction = Reaction::new(
+            7,
+            Bytes::from_static(b"bob"),
+            Bytes::from("👍"),
+            ReactionKind::Remove,
+        );
+
+        match round_trip(Message::Reaction(reaction)) {
+            Message::Reaction(r) => {
+                assert_eq!(r.message_id, 7);
+                assert_eq!(r.emoji, Bytes::from("👍"));
+                assert_eq!(r.kind, ReactionKind::Remove);
+            }
+            other => panic!("Expected a reaction, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reactions_updated_round_trip() {
+        let update = ReactionsUpdated::new(3, vec![(Bytes::from("👍"), 2), (Bytes::from("🎉"), 1)]);
+
+        match round_trip(Message::ReactionsUpdated(update)) {
+            Message::ReactionsUpdated(u) => {
+                assert_eq!(u.message_id, 3);
+                assert_eq!(
+                    u.reactions,
+                    vec![(Bytes::from("👍"), 2), (Bytes::from("🎉"), 1)]
+                );
+            }
+            other => panic!("Expected a reactions update, got {:?}", other),
+        }
+    }
 }
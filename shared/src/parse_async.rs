@@ -1,4 +1,4 @@
-use std::{io::Cursor, usize};
+use std::io::Cursor;
 
 use anyhow::Result;
 use bytes::{Buf, Bytes};
@@ -39,6 +39,20 @@ impl Frame {
             a.push(bulk);
         }
     }
+
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        match self {
+            Self::Array(arr) => {
+                dst.extend_from_slice(format!("*{}\r\n", arr.len()).as_bytes());
+                arr.iter().for_each(|f| f.encode(dst));
+            }
+            Self::Bulk(b) => {
+                dst.extend_from_slice(format!("${}\r\n", b.len()).as_bytes());
+                dst.extend_from_slice(b);
+                dst.extend_from_slice(b"\r\n");
+            }
+        }
+    }
 }
 
 fn parse_line<'a>(cur: &mut Cursor<&'a [u8]>) -> Result<&'a [u8]> {
@@ -140,7 +154,7 @@ mod tests {
 
     #[test]
     fn test_bulk_string() {
-        let mut cur = Cursor::new("$5\r\nhello\r\n".as_bytes());
+        let mut cur = Cursor::new("5\r\nhello\r\n".as_bytes());
         let res = parse_bulk_str(&mut cur).expect("Failed parsing bulk string");
 
         assert_eq!(res, Frame::Bulk(Bytes::from_static(b"hello")))
@@ -170,6 +184,19 @@ mod tests {
         assert_eq!(res, b"a sort of line")
     }
 
+    #[test]
+    fn test_encode_nested_array() {
+        let frame = Frame::Array(vec![
+            Frame::Bulk(Bytes::from_static(b"test")),
+            Frame::Array(vec![Frame::Bulk(Bytes::from_static(b"pak"))]),
+        ]);
+        let mut encoded = Vec::new();
+        frame.encode(&mut encoded);
+
+        assert_eq!(encoded, b"*2\r\n$4\r\ntest\r\n*1\r\n$3\r\npak\r\n");
+        assert_eq!(Frame::parse(&mut Cursor::new(&encoded[..])).unwrap(), frame)
+    }
+
     #[test]
     fn test_parse_nested_array() {
         let mut cur =